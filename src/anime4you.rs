@@ -1,14 +1,25 @@
+use std::cmp::Reverse;
+
 use anyhow::{anyhow, Error};
+use futures::future::join_all;
 use regex::Regex;
 use reqwest::{
     multipart::{self, Part},
-    Client,
+    Url,
 };
 
-use crate::cookie::CookieJar;
+use serde::{Deserialize, Serialize};
 
-#[derive(Clone, Debug)]
-// priority
+use crate::{
+    catalog::{Catalog, CatalogEntry},
+    cookie::Cookie,
+    fail,
+    http::{HttpClient, Role},
+};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+// default priority, can be changed with HostPreference
 #[repr(u32)]
 pub enum Host {
     Vivo = 3,
@@ -18,9 +29,8 @@ pub enum Host {
     Unknown = 0,
 }
 
-const SITE: &str = "https://www.anime4you.one";
+pub const SITE: &str = "https://www.anime4you.one";
 const CAPTCHA_SITE: &str = "https://captcha.anime4you.one";
-const ANIME_LIST: &str = "/speedlist.old.txt";
 
 impl Host {
     pub fn get_from_url(url: &str) -> Host {
@@ -42,9 +52,46 @@ impl Host {
             Host::Unknown
         }
     }
+
+    pub fn from_name(name: &str) -> Result<Host, Error> {
+        match name.to_lowercase().as_str() {
+            "vivo" => Ok(Host::Vivo),
+            "vidoza" => Ok(Host::Vidoza),
+            "gounlimited" => Ok(Host::GoUnlimited),
+            "streamtape" => Ok(Host::Streamtape),
+            other => Err(anyhow!(
+                "Unknown hoster \"{}\", supported are vivo, vidoza, gounlimited and streamtape",
+                other
+            )),
+        }
+    }
 }
 
-#[derive(Clone, Debug)]
+/// Reorders and filters hoster links at runtime.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct HostPreference {
+    /// Hosters tried first, in the given order. All others follow in their default priority.
+    pub prefer: Vec<Host>,
+    /// Hosters never used.
+    pub exclude: Vec<Host>,
+}
+
+impl HostPreference {
+    pub fn apply(&self, links: &mut Vec<HosterLink>) {
+        links.retain(|link| !self.exclude.contains(&link.host));
+        // stable, so the default priority is kept among equally preferred hosters
+        links.sort_by_key(|link| {
+            self.prefer
+                .iter()
+                .position(|host| *host == link.host)
+                .unwrap_or(self.prefer.len())
+        });
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(from = "String", into = "String")]
 pub enum Language {
     JapaneseWithGermanSubtitles,
     German,
@@ -59,9 +106,29 @@ impl Language {
             Language::Other(other) => other.as_str(),
         }
     }
+
+    pub fn same_as(&self, other: &Language) -> bool {
+        self.get_name().eq_ignore_ascii_case(other.get_name())
+    }
+}
+
+impl From<String> for Language {
+    fn from(name: String) -> Language {
+        match name.to_lowercase().as_str() {
+            "gersub" => Language::JapaneseWithGermanSubtitles,
+            "gerdub" => Language::German,
+            _ => Language::Other(name),
+        }
+    }
 }
 
-#[derive(Clone, Debug)]
+impl From<Language> for String {
+    fn from(language: Language) -> String {
+        language.get_name().to_owned()
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct Series {
     pub id: u32,
     pub title: String,
@@ -70,10 +137,9 @@ pub struct Series {
 }
 
 impl Series {
-    pub async fn get_from_id(id: u32) -> Result<Series, Error> {
-        let client = Client::new();
-        let response = client
-            .get(format!("{}/show/1/aid/{}", SITE, id).as_str())
+    pub async fn get_from_id(http: &HttpClient, id: u32) -> Result<Series, Error> {
+        let response = http
+            .get(Role::Site, format!("{}/show/1/aid/{}", SITE, id).as_str())
             .send()
             .await?;
         let response_text = response.text().await?;
@@ -96,98 +162,82 @@ impl Series {
             id,
             title: title.to_string(),
             episodes: episodes.len() as u32,
-            language: match synchronization {
-                "GerSub" => Language::JapaneseWithGermanSubtitles,
-                "GerDub" => Language::German,
-                other => Language::Other(other.to_string()),
-            },
+            language: Language::from(synchronization.to_string()),
         };
         Ok(series)
     }
 
-    pub async fn get_from_name(name: &str, synchronization: &Language) -> Result<Series, Error> {
-        let client = Client::new();
-        let resp = client
-            .get(&format!("{}{}", SITE, ANIME_LIST))
-            .send()
-            .await?
-            .json::<serde_json::Value>()
-            .await?;
-        let mut found = None;
-        for x in resp
-            .as_array()
-            .ok_or(anyhow!("API response isn't an array"))?
-            .iter()
-        {
-            let series = x
-                .as_object()
-                .ok_or(anyhow!("API response array element not an object"))?;
-
-            if series
-                .get("titel")
-                .ok_or(anyhow!(
-                    "API response array element key \"titel\" doesn't exist"
-                ))?
-                .as_str()
-                .ok_or(anyhow!(
-                    "API response array element key \"titel\" is not a string"
-                ))?
-                .to_lowercase()
-                .contains(&name.to_lowercase())
-            {
-                if series
-                    .get("Untertitel")
-                    .ok_or(anyhow!("Series has no \"Untertitel\" value"))?
-                    .as_str()
-                    .ok_or(anyhow!("Series \"Untertitel\" value not a string"))?
-                    == synchronization.get_name()
-                {
-                    found = Option::Some(series);
-                    break;
-                }
-            }
-        }
-        if let Some(found) = found {
-            let series = Series {
-                id: found
-                    .get("aid")
-                    .ok_or(anyhow!("Series has no \"aid\" value"))?
-                    .as_str()
-                    .ok_or(anyhow!("Series \"aid\" value not a string"))?
-                    .parse()?,
-                title: found
-                    .get("titel")
-                    .ok_or(anyhow!("Series has no \"titel\" value"))?
-                    .as_str()
-                    .ok_or(anyhow!("Series \"titel\" value not a string"))?
-                    .to_owned(),
-                episodes: found
-                    .get("Folgen")
-                    .ok_or(anyhow!("Series has no \"Folgen\" value"))?
-                    .as_str()
-                    .ok_or(anyhow!("Series \"Folgen\" value not a string"))?
-                    .parse()?,
-                language: match found
-                    .get("Untertitel")
-                    .ok_or(anyhow!("Series has no \"Untertitel\" value"))?
-                    .as_str()
-                    .ok_or(anyhow!("Series \"Untertitel\" value not a string"))?
-                {
-                    "gersub" => Language::JapaneseWithGermanSubtitles,
-                    "gerdub" => Language::German,
-                    other => Language::Other(other.to_string()),
-                },
-            };
-            Ok(series)
-        } else {
-            Err(anyhow!("Series \"{}\" not found", name))
+    pub fn get_from_name(
+        catalog: &Catalog,
+        name: &str,
+        synchronization: &Language,
+    ) -> Result<Series, Error> {
+        catalog
+            .find(name, synchronization)
+            .map(|entry| Series::from(entry.clone()))
+            .ok_or(anyhow!("Series \"{}\" not found", name))
+    }
+}
+
+impl From<CatalogEntry> for Series {
+    fn from(entry: CatalogEntry) -> Series {
+        Series {
+            id: entry.id,
+            title: entry.title,
+            episodes: entry.episodes,
+            language: entry.language,
         }
     }
 }
 
+/// A link to an episode on one of the hosters.
+#[derive(Clone, Debug, Serialize)]
+pub struct HosterLink {
+    pub host: Host,
+    /// The embed page of the episode on the hoster.
+    pub url: String,
+    /// The hash anime4you resolves the link from, not present for vivo links.
+    pub vidhash: Option<String>,
+    /// The label of the button on the episode page.
+    pub label: String,
+}
+
+/// A series or episode page of anime4you, as copied from the browser.
+#[derive(Clone, Debug)]
+pub struct PageUrl {
+    pub id: u32,
+    pub episode: Option<u32>,
+}
+
+impl PageUrl {
+    pub fn parse(url: &str) -> Result<PageUrl, Error> {
+        let regex = Regex::new(
+            r#"^(?:https?://)?(?:www\.)?anime4you\.one/show/\d+/aid/(\d+)(?:/epi/(\d+))?/?(?:[?#].*)?$"#,
+        )
+        .unwrap();
+        let captures = regex.captures(url.trim()).ok_or(anyhow!(
+            "\"{}\" is not an anime4you url like {}/show/1/aid/123/epi/4/",
+            url,
+            SITE
+        ))?;
+        Ok(PageUrl {
+            id: captures
+                .get(1)
+                .ok_or(anyhow!("Url does not contain a series id"))?
+                .as_str()
+                .parse()?,
+            episode: captures
+                .get(2)
+                .map(|episode| episode.as_str().parse())
+                .transpose()?,
+        })
+    }
+}
+
 #[derive(Clone, Debug)]
 pub struct Captcha {
     pub session: String,
+    #[allow(dead_code)]
     pub id_prefix: String,
     pub question: String,
     pub images: Vec<String>,
@@ -211,77 +261,73 @@ impl Captcha {
 
 pub struct Resolver {
     pub series: Series,
-    pub cookies: CookieJar,
-    pub client: Client,
+    pub http: HttpClient,
 }
 
 impl Resolver {
-    pub fn from_series(series: Series) -> Resolver {
-        let cookies = CookieJar::new();
-        let client = Client::new();
-        Resolver {
-            series,
-            cookies,
-            client,
-        }
+    pub fn from_series(series: Series, http: HttpClient) -> Resolver {
+        Resolver { series, http }
     }
 
     pub async fn populate_cookies(&mut self, episode: u32) -> Result<(), Error> {
-        let cookies_request = self
-            .client
-            .get(
-                format!(
-                    "{}/show/1/aid/{}/epi/{}/#vidplayer",
-                    SITE, self.series.id, episode
-                )
-                .as_str(),
+        let url = Url::parse(
+            format!(
+                "{}/show/1/aid/{}/epi/{}/#vidplayer",
+                SITE, self.series.id, episode
             )
-            .header(
-                "User-Agent",
-                "Mozilla/5.0 (X11; Linux x86_64; rv:84.0) Gecko/20100101 Firefox/84.0",
-            )
-            .header(
-                "Referer",
-                format!("{}/show/1/aid/{}/epi/{}", SITE, self.series.id, episode).as_str(),
-            )
-            .header(
-                "Accept",
-                "text/html,application/xhtml+xml,application/xml;q=0.9,image/webp,*/*;q=0.8",
-            )
-            .header("Dnt", "1")
-            .header("Connection", "keep-alive")
+            .as_str(),
+        )?;
+        let cookies_request = self
+            .http
+            .get(Role::Site, url.as_str())
+            .header("Referer", self.referer(episode).as_str())
             .send()
             .await?;
         if !cookies_request.status().is_success() {
             Err(anyhow!("Failed to fetch cookies."))?
         }
-        self.cookies = CookieJar::parse(
-            cookies_request
-                .headers()
-                .get_all("set-cookie")
-                .iter()
-                .collect(),
-        )?;
+        Ok(())
+    }
+
+    /// The episode page, sent as `Referer` like a browser would.
+    fn referer(&self, episode: u32) -> String {
+        format!("{}/show/1/aid/{}/epi/{}", SITE, self.series.id, episode)
+    }
+
+    /// Whether the client holds cookies for the site, i.e. from a restored session.
+    pub fn has_session(&self) -> Result<bool, Error> {
+        let url = Url::parse(SITE)?;
+        Ok(!self.http.cookies().serialize(&url).is_empty())
+    }
+
+    /// The cookies set by the site and its captcha, which make up the session. Cookies imported
+    /// for other sites are left out.
+    pub fn session_cookies(&self) -> Result<Vec<Cookie>, Error> {
+        let site = Url::parse(SITE)?;
+        let captcha_site = Url::parse(CAPTCHA_SITE)?;
+        Ok(self
+            .http
+            .cookies()
+            .cookies()
+            .into_iter()
+            .filter(|cookie| cookie.matches_host(&site) || cookie.matches_host(&captcha_site))
+            .collect())
+    }
+
+    /// Forgets the cookies of the site, so fresh ones are fetched.
+    pub fn clear_session(&self) -> Result<(), Error> {
+        self.http.cookies().remove_for(&Url::parse(SITE)?);
         Ok(())
     }
 
     pub async fn get_captcha(&mut self, episode: u32) -> Result<Captcha, Error> {
         let captcha_request = self
-            .client
-            .get(format!("{}/Captcheck/api.php?action=new", CAPTCHA_SITE).as_str())
-            //.header("Cookie", self.cookies.serialize())
-            .header(
-                "Referer",
-                format!("{}/show/1/aid/{}/epi/{}", SITE, self.series.id, episode).as_str(),
-            )
-            .header(
-                "Accept",
-                "text/html,application/xhtml+xml,application/xml;q=0.9,image/webp,*/*;q=0.8",
-            )
-            .header(
-                "User-Agent",
-                "Mozilla/5.0 (X11; Linux x86_64; rv:84.0) Gecko/20100101 Firefox/84.0",
+            .http
+            .get(
+                Role::Site,
+                format!("{}/Captcheck/api.php?action=new", CAPTCHA_SITE).as_str(),
             )
+            .header("Referer", self.referer(episode).as_str())
             .send()
             .await?;
 
@@ -336,8 +382,9 @@ impl Resolver {
         image_hash: &str,
     ) -> Result<Vec<u8>, Error> {
         let image_request = self
-            .client
+            .http
             .get(
+                Role::Site,
                 format!(
                     "{}/Captcheck/api.php?action=img&s={}&c={}",
                     CAPTCHA_SITE,
@@ -346,23 +393,12 @@ impl Resolver {
                 )
                 .as_str(),
             )
-            .header(
-                "Referer",
-                format!("{}/show/1/aid/{}/epi/{}", SITE, self.series.id, episode).as_str(),
-            )
-            .header(
-                "Accept",
-                "text/html,application/xhtml+xml,application/xml;q=0.9,image/webp,*/*;q=0.8",
-            )
-            .header(
-                "User-Agent",
-                "Mozilla/5.0 (X11; Linux x86_64; rv:84.0) Gecko/20100101 Firefox/84.0",
-            )
+            .header("Referer", self.referer(episode).as_str())
             .send()
             .await?
             .bytes()
             .await?;
-        Ok(image_request.iter().map(|b| *b).collect())
+        Ok(image_request.to_vec())
     }
 
     pub async fn submit_captcha_image(
@@ -384,25 +420,15 @@ impl Resolver {
                 Part::stream(captcha.session.to_string()),
             );
 
+        let url = Url::parse(format!("{}/Captcheck/humancheck.php", SITE).as_str())?;
         let captcha_request = self
-            .client
-            .post(format!("{}/Captcheck/humancheck.php", SITE).as_str())
-            .header("Cookie", self.cookies.serialize())
-            .header(
-                "Referer",
-                format!("{}/show/1/aid/{}/epi/{}", SITE, self.series.id, episode).as_str(),
-            )
-            .header(
-                "Accept",
-                "text/html,application/xhtml+xml,application/xml;q=0.9,image/webp,*/*;q=0.8",
-            )
-            .header(
-                "User-Agent",
-                "Mozilla/5.0 (X11; Linux x86_64; rv:84.0) Gecko/20100101 Firefox/84.0",
-            )
+            .http
+            .post(Role::Site, url.as_str())
+            .header("Referer", self.referer(episode).as_str())
             .multipart(form)
             .send()
             .await?
+            .error_for_status()?
             .text()
             .await?;
         Ok(if captcha_request.starts_with("FALSE") {
@@ -412,35 +438,169 @@ impl Resolver {
         })
     }
 
-    pub async fn extract_links(&self, response_text: &str) -> Result<Vec<String>, Error> {
-        let vivo_regex = Regex::new(r#"<button href='(.+)' data-src"#)?;
-        let alternative_regex = Regex::new(r#"<button data-src='([^<]*)' class"#)?;
+    pub async fn extract_links(&self, response_text: &str) -> Result<Vec<HosterLink>, Error> {
+        let vivo_regex = Regex::new(r#"<button href='(.+)' data-src[^>]*>([^<]*)"#)?;
+        let alternative_regex = Regex::new(r#"<button data-src='([^<']*)' class[^>]*>([^<]*)"#)?;
         let mut links = Vec::new();
-        if vivo_regex.is_match(response_text) {
-            links.push(
-                vivo_regex
-                    .captures_iter(response_text)
-                    .next()
-                    .ok_or(anyhow!("Failed to match vivo regex"))?
-                    .get(1)
-                    .ok_or(anyhow!("Regex did not find vivo link"))?
-                    .as_str()
-                    .to_string(),
-            );
+        if let Some(capture) = vivo_regex.captures_iter(response_text).next() {
+            let url = capture
+                .get(1)
+                .ok_or(anyhow!("Regex did not find vivo link"))?
+                .as_str()
+                .to_string();
+            links.push(HosterLink {
+                host: Host::get_from_url(url.as_str()),
+                url,
+                vidhash: None,
+                label: capture[2].trim().to_string(),
+            });
         }
-        if alternative_regex.is_match(response_text) {
-            for capture in alternative_regex.captures_iter(response_text) {
-                let response = self
-                    .client
-                    .post(&format!("{}/check_video.php", SITE))
-                    .form(&[("vidhash", capture.get(1).unwrap().as_str())])
-                    .send()
-                    .await?;
-                links.push(response.text().await?.trim().to_string());
+        let checks = alternative_regex
+            .captures_iter(response_text)
+            .map(|capture| async move {
+                let vidhash = capture[1].to_string();
+                let url = async {
+                    let response = self
+                        .http
+                        .post(Role::Site, format!("{}/check_video.php", SITE))
+                        .form(&[("vidhash", vidhash.as_str())])
+                        .send()
+                        .await?;
+                    Ok::<String, Error>(response.text().await?.trim().to_string())
+                }
+                .await
+                .map_err(|err| err.context(format!("Failed to resolve vidhash {}", vidhash)))?;
+                Ok::<HosterLink, Error>(HosterLink {
+                    host: Host::get_from_url(url.as_str()),
+                    url,
+                    vidhash: Some(vidhash),
+                    label: capture[2].trim().to_string(),
+                })
+            });
+        let mut error = None;
+        for link in join_all(checks).await {
+            match link {
+                Ok(link) => links.push(link),
+                Err(err) => {
+                    fail(format!("{:#}", err).as_str());
+                    error = Some(err);
+                }
             }
         }
+        // one broken hoster shouldn't cost the others
+        if let (true, Some(err)) = (links.is_empty(), error) {
+            Err(err)?
+        }
         // sort by priority (defined in Enum)
-        links.sort_by(|a, b| (Host::get_from_url(b) as u32).cmp(&(Host::get_from_url(a) as u32)));
+        links.sort_by_key(|link| Reverse(link.host as u32));
         Ok(links)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn links(hosts: &[(Host, &str)]) -> Vec<HosterLink> {
+        hosts
+            .iter()
+            .map(|(host, label)| HosterLink {
+                host: *host,
+                url: format!("https://{:?}/{}", host, label),
+                vidhash: None,
+                label: label.to_string(),
+            })
+            .collect()
+    }
+
+    fn labels(links: &[HosterLink]) -> Vec<&str> {
+        links.iter().map(|link| link.label.as_str()).collect()
+    }
+
+    /// In the default priority, as returned by `extract_links`.
+    fn default_links() -> Vec<HosterLink> {
+        links(&[
+            (Host::Vidoza, "vidoza 1"),
+            (Host::Vidoza, "vidoza 2"),
+            (Host::Vivo, "vivo"),
+            (Host::GoUnlimited, "gounlimited"),
+            (Host::Streamtape, "streamtape"),
+        ])
+    }
+
+    #[test]
+    fn keeps_default_priority_without_preferences() {
+        let mut links = default_links();
+        HostPreference::default().apply(&mut links);
+        assert_eq!(labels(&links), labels(&default_links()));
+    }
+
+    #[test]
+    fn excludes_hosts() {
+        let mut links = default_links();
+        HostPreference {
+            prefer: Vec::new(),
+            exclude: vec![Host::Vidoza, Host::GoUnlimited],
+        }
+        .apply(&mut links);
+        assert_eq!(labels(&links), ["vivo", "streamtape"]);
+    }
+
+    #[test]
+    fn prefers_hosts_in_the_given_order() {
+        let mut links = default_links();
+        HostPreference {
+            prefer: vec![Host::Streamtape, Host::Vidoza],
+            exclude: vec![Host::Vivo],
+        }
+        .apply(&mut links);
+        assert_eq!(
+            labels(&links),
+            ["streamtape", "vidoza 1", "vidoza 2", "gounlimited"]
+        );
+    }
+
+    #[test]
+    fn parses_page_urls() {
+        for (url, id, episode) in [
+            ("https://www.anime4you.one/show/1/aid/123", 123, None),
+            ("https://www.anime4you.one/show/1/aid/123/", 123, None),
+            (
+                "https://www.anime4you.one/show/1/aid/123/epi/4",
+                123,
+                Some(4),
+            ),
+            (
+                "https://www.anime4you.one/show/1/aid/123/epi/4/#vidplayer",
+                123,
+                Some(4),
+            ),
+            (
+                "http://anime4you.one/show/0/aid/7/epi/12?lang=de",
+                7,
+                Some(12),
+            ),
+            ("  anime4you.one/show/1/aid/123/epi/4/  ", 123, Some(4)),
+        ] {
+            let page = PageUrl::parse(url).unwrap();
+            assert_eq!((page.id, page.episode), (id, episode), "{}", url);
+        }
+    }
+
+    #[test]
+    fn rejects_other_urls() {
+        for url in [
+            "",
+            "123",
+            "https://www.anime4you.one/",
+            "https://www.anime4you.one/show/1/aid/",
+            "https://www.anime4you.one/show/1/aid/abc",
+            "https://www.anime4you.one/show/1/aid/123/epi/",
+            "https://www.anime4you.one.evil.com/show/1/aid/123",
+            "https://example.com/anime4you.one/show/1/aid/123",
+            "https://www.anime4you.one/show/1/aid/99999999999",
+        ] {
+            assert!(PageUrl::parse(url).is_err(), "{}", url);
+        }
+    }
+}
@@ -0,0 +1,256 @@
+use std::{
+    fs::File,
+    path::Path,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use anyhow::{anyhow, Error};
+use reqwest::{
+    header::{ETAG, IF_MODIFIED_SINCE, IF_NONE_MATCH, LAST_MODIFIED},
+    StatusCode,
+};
+use rustbreak::{deser::Bincode, FileDatabase};
+use serde::{de, Deserialize, Deserializer, Serialize};
+
+use crate::{
+    anime4you::{Language, SITE},
+    http::{HttpClient, Role},
+};
+
+const ANIME_LIST: &str = "/speedlist.old.txt";
+
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+struct CachedCatalog {
+    body: String,
+    etag: Option<String>,
+    last_modified: Option<String>,
+    fetched_at: u64,
+}
+
+impl CachedCatalog {
+    fn age(&self) -> Duration {
+        Duration::from_secs(now().saturating_sub(self.fetched_at))
+    }
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+/// The speedlist sends most numbers as strings, but not consistently.
+fn number_or_string<'de, D>(deserializer: D) -> Result<u32, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum NumberOrString {
+        Number(u32),
+        String(String),
+    }
+
+    match NumberOrString::deserialize(deserializer)? {
+        NumberOrString::Number(number) => Ok(number),
+        NumberOrString::String(string) => string.trim().parse().map_err(de::Error::custom),
+    }
+}
+
+/// Reads the speedlist as well as the entries written by `Catalog::export`.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct CatalogEntry {
+    #[serde(alias = "aid", deserialize_with = "number_or_string")]
+    pub id: u32,
+    #[serde(alias = "titel")]
+    pub title: String,
+    #[serde(alias = "Folgen", deserialize_with = "number_or_string")]
+    pub episodes: u32,
+    #[serde(alias = "Untertitel")]
+    pub language: Language,
+}
+
+#[derive(Clone, Debug)]
+pub struct Catalog {
+    pub entries: Vec<CatalogEntry>,
+    /// Entries of the speedlist that didn't fit the model.
+    pub skipped: usize,
+}
+
+impl Catalog {
+    /// Loads the speedlist from the cache at `path`, revalidating it against the site once it is
+    /// older than `ttl`. With `offline` set only the cached copy is used.
+    pub async fn load(
+        http: &HttpClient,
+        path: &Path,
+        ttl: Duration,
+        offline: bool,
+    ) -> Result<Catalog, Error> {
+        let db = FileDatabase::<Option<CachedCatalog>, Bincode>::load_from_path_or_default(path)
+            .or_else(|_| FileDatabase::create_at_path(path, None))?;
+        let cached = db.read(|cached| cached.clone())?;
+        if offline {
+            let cached = cached.ok_or(anyhow!(
+                "No cached catalog available, run once without --offline"
+            ))?;
+            return Catalog::parse(cached.body.as_str());
+        }
+        if let Some(cached) = &cached {
+            if cached.age() < ttl {
+                return Catalog::parse(cached.body.as_str());
+            }
+        }
+
+        let mut request = http.get(Role::Site, format!("{}{}", SITE, ANIME_LIST));
+        if let Some(cached) = &cached {
+            if let Some(etag) = &cached.etag {
+                request = request.header(IF_NONE_MATCH.as_str(), etag.as_str());
+            }
+            if let Some(last_modified) = &cached.last_modified {
+                request = request.header(IF_MODIFIED_SINCE.as_str(), last_modified.as_str());
+            }
+        }
+        let response = request.send().await?;
+        let fresh = if response.status() == StatusCode::NOT_MODIFIED {
+            let mut cached = cached.ok_or(anyhow!("Catalog not modified but nothing is cached"))?;
+            cached.fetched_at = now();
+            cached
+        } else {
+            let response = response.error_for_status()?;
+            let header = |name| {
+                response
+                    .headers()
+                    .get(name)
+                    .and_then(|value| value.to_str().ok())
+                    .map(|value| value.to_owned())
+            };
+            let etag = header(ETAG);
+            let last_modified = header(LAST_MODIFIED);
+            CachedCatalog {
+                body: response.text().await?,
+                etag,
+                last_modified,
+                fetched_at: now(),
+            }
+        };
+        let catalog = Catalog::parse(fresh.body.as_str())?;
+        db.write(|cached| {
+            *cached = Some(fresh);
+        })?;
+        db.save()?;
+        Ok(catalog)
+    }
+
+    /// Searches for the first series whose title contains `name` in the given language.
+    pub fn find(&self, name: &str, language: &Language) -> Option<&CatalogEntry> {
+        let name = name.to_lowercase();
+        self.entries.iter().find(|entry| {
+            entry.title.to_lowercase().contains(&name) && entry.language.same_as(language)
+        })
+    }
+
+    /// Returns every entry of the same show as the series `id`, in all of its languages.
+    pub fn variants(&self, id: u32, title: &str) -> Vec<&CatalogEntry> {
+        let title = self
+            .entries
+            .iter()
+            .find(|entry| entry.id == id)
+            .map_or(title, |entry| entry.title.as_str())
+            .trim()
+            .to_lowercase();
+        self.entries
+            .iter()
+            .filter(|entry| entry.title.trim().to_lowercase() == title)
+            .collect()
+    }
+
+    /// Returns every language in the catalog together with the number of series available in it.
+    pub fn languages(&self) -> Vec<(Language, usize)> {
+        let mut languages: Vec<(Language, usize)> = Vec::new();
+        for entry in &self.entries {
+            match languages
+                .iter_mut()
+                .find(|(language, _)| language.same_as(&entry.language))
+            {
+                Some((_, count)) => *count += 1,
+                None => languages.push((entry.language.clone(), 1)),
+            }
+        }
+        languages.sort_by(|(_, a), (_, b)| b.cmp(a));
+        languages
+    }
+
+    pub fn export(&self, path: &Path) -> Result<(), Error> {
+        serde_json::to_writer_pretty(File::create(path)?, &self.entries)?;
+        Ok(())
+    }
+
+    fn parse(body: &str) -> Result<Catalog, Error> {
+        // entries that don't fit the model are skipped instead of failing the whole catalog
+        let values = serde_json::from_str::<Vec<serde_json::Value>>(body)?;
+        let total = values.len();
+        let entries = values
+            .into_iter()
+            .filter_map(|entry| serde_json::from_value::<CatalogEntry>(entry).ok())
+            .collect::<Vec<CatalogEntry>>();
+        Ok(Catalog {
+            skipped: total - entries.len(),
+            entries,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_numbers_as_strings() {
+        let catalog = Catalog::parse(
+            r#"[
+                {"aid": "123", "titel": "One", "Folgen": "12", "Untertitel": "gersub"},
+                {"aid": 7, "titel": "Two", "Folgen": " 24 ", "Untertitel": "gerdub"}
+            ]"#,
+        )
+        .unwrap();
+        assert_eq!(catalog.skipped, 0);
+        let entries = &catalog.entries;
+        assert_eq!((entries[0].id, entries[0].episodes), (123, 12));
+        assert_eq!((entries[1].id, entries[1].episodes), (7, 24));
+        assert_eq!(entries[0].language, Language::JapaneseWithGermanSubtitles);
+        assert_eq!(entries[1].language, Language::German);
+    }
+
+    #[test]
+    fn counts_skipped_entries() {
+        let catalog = Catalog::parse(
+            r#"[
+                {"aid": "1", "titel": "Fine", "Folgen": "3", "Untertitel": "gersub"},
+                {"aid": "2", "titel": "Empty", "Folgen": "", "Untertitel": "gersub"},
+                {"aid": "3", "titel": "Null", "Folgen": null, "Untertitel": "gersub"},
+                {"aid": "four", "titel": "Text", "Folgen": "3", "Untertitel": "gersub"}
+            ]"#,
+        )
+        .unwrap();
+        assert_eq!(catalog.entries.len(), 1);
+        assert_eq!(catalog.skipped, 3);
+    }
+
+    #[test]
+    fn reads_exported_catalog() {
+        let catalog = Catalog::parse(
+            r#"[{"aid": "5", "titel": "Show", "Folgen": "10", "Untertitel": "engsub"}]"#,
+        )
+        .unwrap();
+        let exported = serde_json::to_string(&catalog.entries).unwrap();
+        let imported = Catalog::parse(exported.as_str()).unwrap();
+        assert_eq!(imported.skipped, 0);
+        let entry = &imported.entries[0];
+        assert_eq!(
+            (entry.id, entry.title.as_str(), entry.episodes),
+            (5, "Show", 10)
+        );
+        assert_eq!(entry.language, Language::Other("engsub".to_string()));
+    }
+}
@@ -0,0 +1,70 @@
+use std::{
+    collections::HashMap,
+    fs,
+    path::{Path, PathBuf},
+    sync::Mutex,
+};
+
+use once_cell::sync::Lazy;
+
+/// A temporary file of a running download.
+struct Entry {
+    /// Whether it can be resumed, if it holds some data.
+    resumable: bool,
+    /// Files that belong to it and are removed along with it.
+    sidecars: Vec<PathBuf>,
+}
+
+/// Temporary files of running downloads.
+static TEMP_FILES: Lazy<Mutex<HashMap<PathBuf, Entry>>> = Lazy::new(|| Mutex::new(HashMap::new()));
+
+/// Keeps track of a temporary file until it is dropped, which happens once the file was renamed
+/// into place or removed.
+pub struct TempFile {
+    path: PathBuf,
+}
+
+impl TempFile {
+    pub fn new(path: &Path, resumable: bool) -> TempFile {
+        TEMP_FILES.lock().unwrap().insert(
+            path.to_owned(),
+            Entry {
+                resumable,
+                sidecars: Vec::new(),
+            },
+        );
+        TempFile {
+            path: path.to_owned(),
+        }
+    }
+
+    /// Removes `sidecar` together with the temporary file.
+    pub fn with_sidecar(self, sidecar: PathBuf) -> TempFile {
+        if let Some(entry) = TEMP_FILES.lock().unwrap().get_mut(&self.path) {
+            entry.sidecars.push(sidecar);
+        }
+        self
+    }
+}
+
+impl Drop for TempFile {
+    fn drop(&mut self) {
+        TEMP_FILES.lock().unwrap().remove(&self.path);
+    }
+}
+
+/// Removes the temporary files of all running downloads, except for resumable ones that already
+/// hold some data.
+pub fn remove_temp_files() {
+    for (path, entry) in TEMP_FILES.lock().unwrap().drain() {
+        let empty = fs::metadata(&path)
+            .map(|metadata| metadata.len() == 0)
+            .unwrap_or(true);
+        if !entry.resumable || empty {
+            let _ = fs::remove_file(&path);
+            for sidecar in entry.sidecars {
+                let _ = fs::remove_file(sidecar);
+            }
+        }
+    }
+}
@@ -0,0 +1,30 @@
+use std::{fs::File, path::Path};
+
+use anyhow::{anyhow, Error};
+use serde::{Deserialize, Serialize};
+
+use crate::{anime4you::HostPreference, http::HttpConfig};
+
+/// Settings read from `config.json` next to the executable or the file given with `--config`.
+/// Every entry is optional, command line options take precedence.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Config {
+    pub http: HttpConfig,
+    pub hosts: HostPreference,
+}
+
+impl Config {
+    /// Loads the config from `path`. A missing file is only an error if `required` is set,
+    /// otherwise the defaults are used.
+    pub fn load(path: &Path, required: bool) -> Result<Config, Error> {
+        if !path.exists() {
+            if required {
+                Err(anyhow!("Config file \"{}\" does not exist", path.display()))?
+            }
+            return Ok(Config::default());
+        }
+        serde_json::from_reader(File::open(path)?)
+            .map_err(|err| anyhow!("Invalid config file \"{}\": {}", path.display(), err))
+    }
+}
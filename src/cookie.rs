@@ -1,71 +1,699 @@
+use std::{
+    fs::{self, File},
+    io::Write,
+    net::IpAddr,
+    path::Path,
+    sync::RwLock,
+    time::{Duration, SystemTime},
+};
+
 use anyhow::{anyhow, Error};
-use regex::Regex;
-use reqwest::header::HeaderValue;
+use reqwest::{cookie::CookieStore, header::HeaderValue, Url};
+use serde::{Deserialize, Serialize};
+
+/// Marks cookies only available to HTTP in `cookies.txt` files, they'd be comments otherwise.
+const HTTP_ONLY_PREFIX: &str = "#HttpOnly_";
+/// Longest lifetime in seconds a `Max-Age` may give a cookie, as browsers limit it to 400 days.
+const MAX_AGE: u64 = 400 * 24 * 60 * 60;
+const MONTHS: [&str; 12] = [
+    "jan", "feb", "mar", "apr", "may", "jun", "jul", "aug", "sep", "oct", "nov", "dec",
+];
 
-#[derive(Clone, Debug)]
+/// Cookies as stored by a user agent following RFC 6265. The jar is used as cookie store of the
+/// HTTP clients, so it is shared and only needs `&self`.
+#[derive(Debug, Default)]
 pub struct CookieJar {
-    pub cookies: Vec<Cookie>,
+    cookies: RwLock<Vec<Cookie>>,
 }
 
 impl CookieJar {
     pub fn new() -> CookieJar {
-        CookieJar {
-            cookies: Vec::new(),
+        CookieJar::default()
+    }
+
+    /// A copy of the stored cookies that haven't expired yet.
+    pub fn cookies(&self) -> Vec<Cookie> {
+        self.cookies
+            .read()
+            .unwrap()
+            .iter()
+            .filter(|cookie| !cookie.is_expired())
+            .cloned()
+            .collect()
+    }
+
+    /// Stores a cookie, replacing the one with the same name, domain and path. An already
+    /// expired cookie only removes the stored one, which is how servers delete cookies.
+    pub fn add_cookie(&self, mut cookie: Cookie) {
+        let mut cookies = self.cookies.write().unwrap();
+        if let Some(index) = cookies.iter().position(|c| c.same_as(&cookie)) {
+            cookie.created = cookies.remove(index).created;
+        }
+        if !cookie.is_expired() {
+            cookies.push(cookie);
         }
     }
 
-    pub fn add_cookie(&mut self, cookie: Cookie) {
-        if cookie.value != String::from("deleted") {
-            if let Some(duplicated_index) = self.cookies.iter().position(|c| c.key == cookie.key) {
-                self.cookies.remove(duplicated_index);
+    /// Removes every cookie sent along with requests to `url`.
+    pub fn remove_for(&self, url: &Url) {
+        self.cookies
+            .write()
+            .unwrap()
+            .retain(|cookie| !cookie.matches(url));
+    }
+
+    /// The value of the `Cookie` header for a request to `url`: the matching cookies, longer
+    /// paths first and older cookies first among equal paths.
+    pub fn serialize(&self, url: &Url) -> String {
+        let cookies = self.cookies.read().unwrap();
+        let mut cookies = cookies
+            .iter()
+            .filter(|cookie| cookie.matches(url))
+            .collect::<Vec<&Cookie>>();
+        cookies.sort_by(|a, b| {
+            b.path
+                .len()
+                .cmp(&a.path.len())
+                .then(a.created.cmp(&b.created))
+        });
+        cookies
+            .into_iter()
+            .map(|cookie| cookie.serialize())
+            .collect::<Vec<String>>()
+            .join("; ")
+    }
+
+    /// Stores the cookies of the `Set-Cookie` headers of a response from `url`. Cookies the
+    /// response isn't allowed to set are ignored, as the RFC demands.
+    pub fn store<'a, I>(&self, url: &Url, headers: I)
+    where
+        I: IntoIterator<Item = &'a HeaderValue>,
+    {
+        for header in headers {
+            if let Ok(cookie) = header
+                .to_str()
+                .map_err(Error::from)
+                .and_then(|header| Cookie::parse(header, url))
+            {
+                self.add_cookie(cookie);
+            }
+        }
+    }
+}
+
+impl CookieJar {
+    /// Adds the cookies of a Netscape `cookies.txt` file, as exported by browser extensions and
+    /// used by youtube-dl. Returns the number of cookies read.
+    pub fn load_netscape(&self, path: &Path) -> Result<usize, Error> {
+        let content = fs::read_to_string(path)?;
+        let mut count = 0;
+        for (number, line) in content.lines().enumerate() {
+            let line = line.trim_end_matches('\r');
+            let (line, http_only) = match line.strip_prefix(HTTP_ONLY_PREFIX) {
+                Some(line) => (line, true),
+                None => (line, false),
             };
-            self.cookies.push(cookie);
+            if line.trim().is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let mut cookie = Cookie::from_netscape(line)
+                .map_err(|err| anyhow!("{}, line {}: {}", path.display(), number + 1, err))?;
+            cookie.http_only = http_only;
+            self.add_cookie(cookie);
+            count += 1;
         }
+        Ok(count)
     }
 
-    pub fn serialize(&self) -> String {
-        let mut buffer: String = String::new();
-        for value in &self.cookies {
-            buffer.push_str(&value.serialize());
-            buffer.push_str("; ");
-        }
-        buffer.chars().take(buffer.chars().count() - 2).collect()
-    }
-
-    pub fn parse(headers: Vec<&HeaderValue>) -> Result<CookieJar, Error> {
-        let mut cookie_jar = CookieJar::new();
-        let header_value_regex = Regex::new(r#"(.*?)=(.*?);"#).unwrap();
-        for header_value in headers {
-            let header_captures = header_value_regex
-                .captures(header_value.to_str()?)
-                .expect("Not valid header");
-            let header_key = header_captures
-                .get(1)
-                .ok_or(anyhow!("Not valid header key"))
-                .unwrap()
-                .as_str();
-            let header_value = header_captures
-                .get(2)
-                .ok_or(anyhow!("Not valid header value"))
-                .unwrap()
-                .as_str();
-            cookie_jar.add_cookie(Cookie {
-                key: header_key.to_string(),
-                value: header_value.to_string(),
-            });
+    /// Writes the cookies to a Netscape `cookies.txt` file, session cookies included.
+    pub fn save_netscape(&self, path: &Path) -> Result<(), Error> {
+        let mut file = File::create(path)?;
+        writeln!(file, "# Netscape HTTP Cookie File")?;
+        for cookie in self.cookies() {
+            writeln!(file, "{}", cookie.to_netscape())?;
+        }
+        Ok(())
+    }
+}
+
+impl CookieStore for CookieJar {
+    fn set_cookies(&self, cookie_headers: &mut dyn Iterator<Item = &HeaderValue>, url: &Url) {
+        self.store(url, cookie_headers);
+    }
+
+    fn cookies(&self, url: &Url) -> Option<HeaderValue> {
+        let cookies = self.serialize(url);
+        if cookies.is_empty() {
+            return None;
         }
-        Ok(cookie_jar)
+        HeaderValue::from_str(cookies.as_str()).ok()
     }
 }
 
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct Cookie {
     pub key: String,
     pub value: String,
+    /// Without the leading dot, lowercase.
+    pub domain: String,
+    /// Set if the cookie had no `Domain` attribute, it is only sent to that exact host then.
+    pub host_only: bool,
+    pub path: String,
+    /// `None` for session cookies.
+    pub expires: Option<SystemTime>,
+    pub secure: bool,
+    pub http_only: bool,
+    pub created: SystemTime,
 }
 
 impl Cookie {
+    /// Parses a `Set-Cookie` header received from `url` (RFC 6265, section 5.2 and 5.3).
+    pub fn parse(header: &str, url: &Url) -> Result<Cookie, Error> {
+        let host = url
+            .host_str()
+            .ok_or(anyhow!("Cookies need a host"))?
+            .to_lowercase();
+        let mut parts = header.split(';');
+        let pair = parts.next().unwrap_or("");
+        let separator = pair
+            .find('=')
+            .ok_or(anyhow!("Not valid cookie: missing \"=\""))?;
+        let key = pair[..separator].trim();
+        if key.is_empty() {
+            Err(anyhow!("Not valid cookie: empty name"))?
+        }
+        let mut cookie = Cookie {
+            key: key.to_string(),
+            value: pair[separator + 1..].trim().to_string(),
+            domain: host.clone(),
+            host_only: true,
+            path: default_path(url),
+            expires: None,
+            secure: false,
+            http_only: false,
+            created: SystemTime::now(),
+        };
+
+        let mut max_age = None;
+        let mut expires = None;
+        let mut domain = None;
+        for attribute in parts {
+            let (name, value) = match attribute.find('=') {
+                Some(index) => (&attribute[..index], attribute[index + 1..].trim()),
+                None => (attribute, ""),
+            };
+            match name.trim().to_lowercase().as_str() {
+                "expires" => {
+                    if let Some(date) = parse_cookie_date(value) {
+                        expires = Some(date);
+                    }
+                }
+                "max-age" => {
+                    if let Ok(seconds) = value.parse::<i64>() {
+                        max_age = Some(if seconds <= 0 {
+                            SystemTime::UNIX_EPOCH
+                        } else {
+                            SystemTime::now() + Duration::from_secs((seconds as u64).min(MAX_AGE))
+                        });
+                    }
+                }
+                "domain" if !value.is_empty() => {
+                    domain = Some(value.trim_start_matches('.').to_lowercase());
+                }
+                "path" if value.starts_with('/') => cookie.path = value.to_string(),
+                "secure" => cookie.secure = true,
+                "httponly" => cookie.http_only = true,
+                _ => (),
+            }
+        }
+        cookie.expires = max_age.or(expires);
+        if let Some(domain) = domain {
+            if !domain_matches(host.as_str(), domain.as_str()) {
+                Err(anyhow!(
+                    "Not valid cookie: {} may not set cookies for {}",
+                    host,
+                    domain
+                ))?
+            }
+            cookie.domain = domain;
+            cookie.host_only = false;
+        }
+        Ok(cookie)
+    }
+
+    /// Parses a line of a Netscape `cookies.txt` file: domain, subdomains flag, path, secure
+    /// flag, expiry as unix time (0 for session cookies), name and value, separated by tabs.
+    fn from_netscape(line: &str) -> Result<Cookie, Error> {
+        let fields = line.splitn(7, '\t').collect::<Vec<&str>>();
+        if fields.len() < 7 {
+            Err(anyhow!(
+                "Expected 7 tab separated fields, got {}",
+                fields.len()
+            ))?
+        }
+        let flag = |value: &str| -> Result<bool, Error> {
+            match value.to_uppercase().as_str() {
+                "TRUE" => Ok(true),
+                "FALSE" => Ok(false),
+                _ => Err(anyhow!("Expected TRUE or FALSE, got \"{}\"", value)),
+            }
+        };
+        let domain = fields[0].to_lowercase();
+        if domain.trim_start_matches('.').is_empty() || fields[5].is_empty() {
+            Err(anyhow!("Missing domain or name"))?
+        }
+        let expires = match fields[4]
+            .parse::<u64>()
+            .map_err(|_| anyhow!("Not a valid expiry: \"{}\"", fields[4]))?
+        {
+            0 => None,
+            expires => Some(
+                SystemTime::UNIX_EPOCH
+                    .checked_add(Duration::from_secs(expires))
+                    .ok_or(anyhow!("Not a valid expiry: \"{}\"", fields[4]))?,
+            ),
+        };
+        Ok(Cookie {
+            key: fields[5].to_string(),
+            value: fields[6].to_string(),
+            host_only: !flag(fields[1])? && !domain.starts_with('.'),
+            domain: domain.trim_start_matches('.').to_string(),
+            path: if fields[2].starts_with('/') {
+                fields[2].to_string()
+            } else {
+                "/".to_string()
+            },
+            expires,
+            secure: flag(fields[3])?,
+            http_only: false,
+            created: SystemTime::now(),
+        })
+    }
+
+    fn to_netscape(&self) -> String {
+        let flag = |value: bool| if value { "TRUE" } else { "FALSE" };
+        format!(
+            "{}{}{}\t{}\t{}\t{}\t{}\t{}\t{}",
+            if self.http_only { HTTP_ONLY_PREFIX } else { "" },
+            if self.host_only { "" } else { "." },
+            self.domain,
+            flag(!self.host_only),
+            self.path,
+            flag(self.secure),
+            self.expires
+                .and_then(|expires| expires.duration_since(SystemTime::UNIX_EPOCH).ok())
+                .map(|expires| expires.as_secs())
+                .unwrap_or(0),
+            self.key,
+            self.value
+        )
+    }
+
+    pub fn is_expired(&self) -> bool {
+        self.expires
+            .map(|expires| expires <= SystemTime::now())
+            .unwrap_or(false)
+    }
+
+    /// Whether the cookie belongs to the host of `url`, whatever its path.
+    pub fn matches_host(&self, url: &Url) -> bool {
+        let host = match url.host_str() {
+            Some(host) => host.to_lowercase(),
+            None => return false,
+        };
+        if self.host_only {
+            host == self.domain
+        } else {
+            domain_matches(host.as_str(), self.domain.as_str())
+        }
+    }
+
+    /// Whether the cookie is sent along with a request to `url`.
+    pub fn matches(&self, url: &Url) -> bool {
+        self.matches_host(url)
+            && path_matches(url.path(), self.path.as_str())
+            && (!self.secure || url.scheme() == "https")
+            && !self.is_expired()
+    }
+
+    fn same_as(&self, other: &Cookie) -> bool {
+        self.key == other.key && self.domain == other.domain && self.path == other.path
+    }
+
     pub fn serialize(&self) -> String {
         format!("{}={}", self.key, self.value)
     }
 }
+
+fn domain_matches(host: &str, domain: &str) -> bool {
+    host == domain
+        || (host.ends_with(domain)
+            && host[..host.len() - domain.len()].ends_with('.')
+            && host.parse::<IpAddr>().is_err())
+}
+
+fn path_matches(path: &str, cookie_path: &str) -> bool {
+    path == cookie_path
+        || (path.starts_with(cookie_path)
+            && (cookie_path.ends_with('/') || path[cookie_path.len()..].starts_with('/')))
+}
+
+/// Parses the date of an `Expires` attribute the lenient way of RFC 6265, section 5.1.1, which
+/// also accepts the formats of old servers like `Thu, 19-Oct-2027 10:00:00 GMT`.
+fn parse_cookie_date(value: &str) -> Option<SystemTime> {
+    let delimiter = |c: char| matches!(c, '\t' | '\x20'..='\x2f' | '\x3b'..='\x40' | '\x5b'..='\x60' | '\x7b'..='\x7e');
+    let mut time = None;
+    let mut day = None;
+    let mut month = None;
+    let mut year = None;
+    for token in value.split(delimiter).filter(|token| !token.is_empty()) {
+        if time.is_none() {
+            if let Some(parsed) = parse_time(token) {
+                time = Some(parsed);
+                continue;
+            }
+        }
+        if day.is_none() {
+            if let Some((parsed, _)) = leading_digits(token, 1, 2) {
+                day = Some(parsed);
+                continue;
+            }
+        }
+        if month.is_none() {
+            let name = token.get(..3).unwrap_or("").to_lowercase();
+            if let Some(index) = MONTHS.iter().position(|month| *month == name) {
+                month = Some(index as u32 + 1);
+                continue;
+            }
+        }
+        if year.is_none() {
+            if let Some((parsed, _)) = leading_digits(token, 2, 4) {
+                year = Some(parsed);
+            }
+        }
+    }
+    let ((hour, minute, second), day, month, mut year) = (time?, day?, month?, year?);
+    if (70..=99).contains(&year) {
+        year += 1900;
+    } else if year <= 69 {
+        year += 2000;
+    }
+    let leap = year % 4 == 0 && (year % 100 != 0 || year % 400 == 0);
+    let days_in_month = match month {
+        2 if leap => 29,
+        2 => 28,
+        4 | 6 | 9 | 11 => 30,
+        _ => 31,
+    };
+    if year < 1601 || day < 1 || day > days_in_month || hour > 23 || minute > 59 || second > 59 {
+        return None;
+    }
+    let seconds = days_from_civil(year as i64, month as i64, day as i64) * 86400
+        + (hour * 3600 + minute * 60 + second) as i64;
+    // dates before 1970 just mean the cookie expired
+    Some(SystemTime::UNIX_EPOCH + Duration::from_secs(seconds.max(0) as u64))
+}
+
+/// `hh:mm:ss`, each with one or two digits, optionally followed by anything but a digit.
+fn parse_time(token: &str) -> Option<(u32, u32, u32)> {
+    let (hour, rest) = leading_digits(token, 1, 2)?;
+    let (minute, rest) = leading_digits(rest.strip_prefix(':')?, 1, 2)?;
+    let (second, _) = leading_digits(rest.strip_prefix(':')?, 1, 2)?;
+    Some((hour, minute, second))
+}
+
+/// The number made of `min` to `max` digits at the start of `token`, if no further digit follows,
+/// and the rest of the token.
+fn leading_digits(token: &str, min: usize, max: usize) -> Option<(u32, &str)> {
+    let count = token.bytes().take_while(u8::is_ascii_digit).count();
+    if count < min || count > max {
+        return None;
+    }
+    Some((token[..count].parse().ok()?, &token[count..]))
+}
+
+/// Days since 1970-01-01 of a date in the proleptic Gregorian calendar.
+fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year - era * 400;
+    let day_of_year = (153 * ((month + 9) % 12) + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146097 + day_of_era - 719468
+}
+
+/// The directory of the request path, used if a cookie has no `Path` attribute.
+fn default_path(url: &Url) -> String {
+    let path = url.path();
+    match path.rfind('/') {
+        Some(index) if index > 0 && path.starts_with('/') => path[..index].to_string(),
+        _ => "/".to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn url(url: &str) -> Url {
+        Url::parse(url).unwrap()
+    }
+
+    fn unix_time(cookie: &Cookie) -> Option<u64> {
+        cookie.expires.map(|expires| {
+            expires
+                .duration_since(SystemTime::UNIX_EPOCH)
+                .unwrap()
+                .as_secs()
+        })
+    }
+
+    #[test]
+    fn parses_cookie_without_attributes() {
+        let cookie = Cookie::parse("session=abc123", &url("https://example.com/a/b")).unwrap();
+        assert_eq!(cookie.key, "session");
+        assert_eq!(cookie.value, "abc123");
+        assert_eq!(cookie.domain, "example.com");
+        assert!(cookie.host_only);
+        assert_eq!(cookie.expires, None);
+        assert!(!cookie.secure && !cookie.http_only);
+    }
+
+    #[test]
+    fn rejects_cookie_without_name() {
+        assert!(Cookie::parse("=value", &url("https://example.com/")).is_err());
+        assert!(Cookie::parse("value", &url("https://example.com/")).is_err());
+    }
+
+    #[test]
+    fn max_age_wins_over_expires() {
+        let url = url("https://example.com/");
+        for header in [
+            "a=b; Expires=Thu, 01 Jan 1970 00:00:01 GMT; Max-Age=3600",
+            "a=b; Max-Age=3600; Expires=Thu, 01 Jan 1970 00:00:01 GMT",
+        ] {
+            let cookie = Cookie::parse(header, &url).unwrap();
+            assert!(!cookie.is_expired(), "{}", header);
+        }
+        let cookie = Cookie::parse(
+            "a=b; Max-Age=0; Expires=Wed, 21 Oct 2099 07:28:00 GMT",
+            &url,
+        )
+        .unwrap();
+        assert!(cookie.is_expired());
+    }
+
+    #[test]
+    fn huge_max_age_does_not_overflow() {
+        let cookie = Cookie::parse(
+            "a=b; Max-Age=9223372036854775807",
+            &url("https://example.com/"),
+        )
+        .unwrap();
+        assert!(cookie.expires.is_some());
+        assert!(!cookie.is_expired());
+    }
+
+    #[test]
+    fn parses_cookie_dates() {
+        let url = url("https://example.com/");
+        for (date, expected) in [
+            ("Wed, 21 Oct 2015 07:28:00 GMT", Some(1445412480)),
+            ("Wednesday, 21-Oct-15 07:28:00 GMT", Some(1445412480)),
+            ("Wed Oct 21 07:28:00 2015", Some(1445412480)),
+            ("Tue, 19-Oct-2027 10:00:00 GMT", Some(1823940000)),
+            ("19 oct 2027 10:00:00", Some(1823940000)),
+            ("Thu, 01 Jan 1970 00:00:00 GMT", Some(0)),
+            ("Wed, 31 Dec 1600 23:59:59 GMT", None),
+            ("Sat, 30 Feb 2027 10:00:00 GMT", None),
+            ("Tue, 19 Oct 2027 24:00:00 GMT", None),
+            ("Tue, 19 Oct 2027", None),
+            ("tomorrow", None),
+        ] {
+            let cookie = Cookie::parse(format!("a=b; Expires={}", date).as_str(), &url).unwrap();
+            assert_eq!(unix_time(&cookie), expected, "{}", date);
+        }
+    }
+
+    #[test]
+    fn checks_domain_attribute() {
+        let url = url("https://www.example.com/");
+        let cookie = Cookie::parse("a=b; Domain=.Example.com", &url).unwrap();
+        assert_eq!(cookie.domain, "example.com");
+        assert!(!cookie.host_only);
+        assert!(Cookie::parse("a=b; Domain=other.com", &url).is_err());
+        assert!(Cookie::parse("a=b; Domain=ample.com", &url).is_err());
+        assert!(Cookie::parse("a=b; Domain=sub.www.example.com", &url).is_err());
+    }
+
+    #[test]
+    fn defaults_path_to_directory() {
+        for (request, expected) in [
+            ("https://example.com", "/"),
+            ("https://example.com/", "/"),
+            ("https://example.com/page", "/"),
+            ("https://example.com/a/b/page", "/a/b"),
+        ] {
+            let cookie = Cookie::parse("a=b", &url(request)).unwrap();
+            assert_eq!(cookie.path, expected, "{}", request);
+        }
+        let cookie = Cookie::parse("a=b; Path=relative", &url("https://example.com/a/b")).unwrap();
+        assert_eq!(cookie.path, "/a");
+        let cookie = Cookie::parse("a=b; Path=/", &url("https://example.com/a/b")).unwrap();
+        assert_eq!(cookie.path, "/");
+    }
+
+    fn jar(url: &Url, headers: &[&str]) -> CookieJar {
+        let jar = CookieJar::new();
+        for header in headers {
+            jar.add_cookie(Cookie::parse(header, url).unwrap());
+        }
+        jar
+    }
+
+    #[test]
+    fn serializes_cookies_by_domain() {
+        let jar = jar(
+            &url("https://www.example.com/"),
+            &["host=1", "domain=2; Domain=example.com"],
+        );
+        assert_eq!(
+            jar.serialize(&url("https://www.example.com/")),
+            "host=1; domain=2"
+        );
+        assert_eq!(
+            jar.serialize(&url("https://WWW.Example.com/")),
+            "host=1; domain=2"
+        );
+        assert_eq!(jar.serialize(&url("https://example.com/")), "domain=2");
+        assert_eq!(
+            jar.serialize(&url("https://sub.www.example.com/")),
+            "domain=2"
+        );
+        assert_eq!(jar.serialize(&url("https://notexample.com/")), "");
+        assert_eq!(jar.serialize(&url("https://other.com/")), "");
+    }
+
+    #[test]
+    fn serializes_cookies_by_path() {
+        let jar = jar(
+            &url("https://example.com/"),
+            &["root=1", "show=2; Path=/show", "episode=3; Path=/show/1/"],
+        );
+        // longer paths first
+        assert_eq!(
+            jar.serialize(&url("https://example.com/show/1/aid")),
+            "episode=3; show=2; root=1"
+        );
+        assert_eq!(
+            jar.serialize(&url("https://example.com/show")),
+            "show=2; root=1"
+        );
+        assert_eq!(
+            jar.serialize(&url("https://example.com/show/")),
+            "show=2; root=1"
+        );
+        assert_eq!(jar.serialize(&url("https://example.com/shows")), "root=1");
+        assert_eq!(jar.serialize(&url("https://example.com/")), "root=1");
+    }
+
+    #[test]
+    fn sends_secure_cookies_only_over_https() {
+        let jar = jar(
+            &url("https://example.com/"),
+            &["plain=1", "secret=2; Secure"],
+        );
+        assert_eq!(
+            jar.serialize(&url("https://example.com/")),
+            "plain=1; secret=2"
+        );
+        assert_eq!(jar.serialize(&url("http://example.com/")), "plain=1");
+    }
+
+    #[test]
+    fn replaces_and_deletes_cookies() {
+        let url = url("https://example.com/");
+        let jar = jar(&url, &["a=1", "b=2", "a=3"]);
+        assert_eq!(jar.serialize(&url), "a=3; b=2");
+        jar.store(&url, [&HeaderValue::from_static("a=deleted; Max-Age=0")]);
+        assert_eq!(jar.serialize(&url), "b=2");
+        jar.store(
+            &url,
+            [&HeaderValue::from_static(
+                "b=deleted; Expires=Thu, 01 Jan 1970 00:00:00 GMT",
+            )],
+        );
+        assert_eq!(jar.serialize(&url), "");
+        assert!(jar.cookies().is_empty());
+    }
+
+    #[test]
+    fn ignores_cookies_for_other_domains() {
+        let site = url("https://www.example.com/");
+        let jar = CookieJar::new();
+        jar.store(
+            &site,
+            [
+                &HeaderValue::from_static("a=1; Domain=other.com"),
+                &HeaderValue::from_static("b=2"),
+            ],
+        );
+        assert_eq!(jar.serialize(&url("https://other.com/")), "");
+        assert_eq!(jar.serialize(&site), "b=2");
+    }
+
+    #[test]
+    fn netscape_round_trip() {
+        let content = "# Netscape HTTP Cookie File\n\
+                       .example.com\tTRUE\t/\tFALSE\t4102444800\tsession\tabc\n\
+                       #HttpOnly_www.example.com\tFALSE\t/a\tTRUE\t0\ttoken\tx=y\n";
+        let dir = tempfile::tempdir().unwrap();
+        let input = dir.path().join("in.txt");
+        let output = dir.path().join("out.txt");
+        fs::write(&input, content).unwrap();
+        let jar = CookieJar::new();
+        assert_eq!(jar.load_netscape(&input).unwrap(), 2);
+        jar.save_netscape(&output).unwrap();
+        assert_eq!(fs::read_to_string(&output).unwrap(), content);
+
+        let cookies = jar.cookies();
+        assert!(!cookies[0].host_only && !cookies[0].http_only);
+        assert_eq!(unix_time(&cookies[0]), Some(4102444800));
+        assert!(cookies[1].host_only && cookies[1].http_only && cookies[1].secure);
+        assert_eq!(cookies[1].value, "x=y");
+        assert_eq!(cookies[1].expires, None);
+    }
+
+    #[test]
+    fn rejects_invalid_netscape_lines() {
+        for line in [
+            "example.com\tFALSE\t/\tFALSE\t0\tname",
+            "example.com\tMAYBE\t/\tFALSE\t0\tname\tvalue",
+            "example.com\tFALSE\t/\tFALSE\tsoon\tname\tvalue",
+            "example.com\tFALSE\t/\tFALSE\t18446744073709551615\tname\tvalue",
+            "\tFALSE\t/\tFALSE\t0\tname\tvalue",
+        ] {
+            assert!(Cookie::from_netscape(line).is_err(), "{}", line);
+        }
+    }
+}
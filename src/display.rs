@@ -0,0 +1,249 @@
+use std::{
+    io::{self, IsTerminal, Write},
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use once_cell::sync::Lazy;
+
+use crate::downloader::Progress;
+
+/// How often the status lines are redrawn at most.
+const REDRAW_INTERVAL: Duration = Duration::from_millis(100);
+/// How often a download is logged without a terminal, if its size is unknown.
+const LOG_INTERVAL: Duration = Duration::from_secs(10);
+const BAR_WIDTH: usize = 20;
+
+struct Task {
+    id: u64,
+    label: String,
+    progress: Option<Progress>,
+    /// The next percentage logged without a terminal.
+    next_log: f64,
+    logged_at: Instant,
+}
+
+/// The log lines and, on a terminal, one status line per running download below them.
+struct Display {
+    /// Set to keep stdout free for output meant for other programs.
+    stderr: bool,
+    tty: bool,
+    tasks: Vec<Task>,
+    next_id: u64,
+    /// Status lines currently on screen, removed before anything else is printed.
+    drawn: usize,
+    drawn_at: Option<Instant>,
+    queued: u32,
+    finished: u32,
+}
+
+static DISPLAY: Lazy<Mutex<Display>> = Lazy::new(|| {
+    Mutex::new(Display {
+        stderr: false,
+        tty: io::stdout().is_terminal(),
+        tasks: Vec::new(),
+        next_id: 0,
+        drawn: 0,
+        drawn_at: None,
+        queued: 0,
+        finished: 0,
+    })
+});
+
+impl Display {
+    fn output(&self) -> Box<dyn Write> {
+        if self.stderr {
+            Box::new(io::stderr().lock())
+        } else {
+            Box::new(io::stdout().lock())
+        }
+    }
+
+    fn clear(&mut self, out: &mut impl Write) {
+        if self.drawn > 0 {
+            // to the start of the first status line and erase everything below
+            let _ = write!(out, "\x1b[{}F\x1b[J", self.drawn);
+            self.drawn = 0;
+        }
+    }
+
+    fn draw(&mut self, out: &mut impl Write) {
+        self.clear(out);
+        self.drawn_at = Some(Instant::now());
+        if self.tasks.is_empty() {
+            return;
+        }
+        for task in &self.tasks {
+            let _ = writeln!(out, "    {}", status_line(task));
+        }
+        let speed: f64 = self
+            .tasks
+            .iter()
+            .filter_map(|task| task.progress.map(|progress| progress.speed))
+            .sum();
+        let done = self.finished.min(self.queued);
+        let _ = writeln!(
+            out,
+            "    {} {}/{} episodes, {}/s",
+            bar(done as f64 / self.queued.max(1) as f64),
+            done,
+            self.queued,
+            format_bytes(speed as u64)
+        );
+        self.drawn = self.tasks.len() + 1;
+    }
+}
+
+fn status_line(task: &Task) -> String {
+    match task.progress {
+        None => format!("{} starting...", task.label),
+        Some(progress) => {
+            let mut line = format!("{} ", task.label);
+            if let Some(percent) = progress.percent() {
+                line.push_str(format!("{} {:>3.0}% ", bar(percent / 100.0), percent).as_str());
+            }
+            line.push_str(format_bytes(progress.downloaded).as_str());
+            line.push_str(format!(" {}/s", format_bytes(progress.speed as u64)).as_str());
+            if let (Some(total), true) = (progress.total, progress.speed > 0.0) {
+                let left = total.saturating_sub(progress.downloaded) as f64 / progress.speed;
+                line.push_str(format!(" ETA {}", format_duration(left as u64)).as_str());
+            }
+            line
+        }
+    }
+}
+
+fn bar(fraction: f64) -> String {
+    let filled = ((fraction.clamp(0.0, 1.0) * BAR_WIDTH as f64).round() as usize).min(BAR_WIDTH);
+    format!("[{}{}]", "#".repeat(filled), "-".repeat(BAR_WIDTH - filled))
+}
+
+fn format_duration(seconds: u64) -> String {
+    if seconds >= 3600 {
+        format!(
+            "{}:{:02}:{:02}",
+            seconds / 3600,
+            seconds / 60 % 60,
+            seconds % 60
+        )
+    } else {
+        format!("{}:{:02}", seconds / 60, seconds % 60)
+    }
+}
+
+fn format_bytes(bytes: u64) -> String {
+    const UNITS: [&str; 4] = ["KiB", "MiB", "GiB", "TiB"];
+    if bytes < 1024 {
+        return format!("{} B", bytes);
+    }
+    let mut size = bytes as f64 / 1024.0;
+    let mut unit = 0;
+    while size >= 1024.0 && unit < UNITS.len() - 1 {
+        size /= 1024.0;
+        unit += 1;
+    }
+    format!("{:.1} {}", size, UNITS[unit])
+}
+
+/// Prints the log and status lines to stderr instead of stdout.
+pub fn use_stderr() {
+    let mut display = DISPLAY.lock().unwrap();
+    display.stderr = true;
+    display.tty = io::stderr().is_terminal();
+}
+
+/// Prints a log line above the status lines.
+pub fn log(line: &str) {
+    let mut display = DISPLAY.lock().unwrap();
+    let mut out = display.output();
+    if display.tty {
+        display.clear(&mut out);
+        let _ = writeln!(out, "{}", line);
+        display.draw(&mut out);
+    } else {
+        let _ = writeln!(out, "{}", line);
+    }
+    let _ = out.flush();
+}
+
+/// Counts an episode towards the overall progress.
+pub fn queue_episode() {
+    DISPLAY.lock().unwrap().queued += 1;
+}
+
+/// Marks a queued episode as done, whether it succeeded or not.
+pub fn finish_episode() {
+    DISPLAY.lock().unwrap().finished += 1;
+}
+
+/// A running download shown in the display until it is dropped.
+pub struct DownloadStatus {
+    id: u64,
+}
+
+impl DownloadStatus {
+    pub fn new(label: String) -> DownloadStatus {
+        let mut display = DISPLAY.lock().unwrap();
+        let id = display.next_id;
+        display.next_id += 1;
+        display.tasks.push(Task {
+            id,
+            label,
+            progress: None,
+            next_log: 10.0,
+            logged_at: Instant::now(),
+        });
+        if display.tty {
+            let mut out = display.output();
+            display.draw(&mut out);
+            let _ = out.flush();
+        }
+        DownloadStatus { id }
+    }
+
+    pub fn update(&self, progress: &Progress) {
+        let mut display = DISPLAY.lock().unwrap();
+        let tty = display.tty;
+        let redraw = display
+            .drawn_at
+            .map_or(true, |drawn_at| drawn_at.elapsed() >= REDRAW_INTERVAL);
+        let mut out = display.output();
+        let task = match display.tasks.iter_mut().find(|task| task.id == self.id) {
+            Some(task) => task,
+            None => return,
+        };
+        task.progress = Some(*progress);
+        if tty {
+            if redraw || progress.finished {
+                display.draw(&mut out);
+            }
+        } else if !progress.finished {
+            // every 10 percent, or every now and then if the size is unknown
+            let due = match progress.percent() {
+                Some(percent) => percent >= task.next_log,
+                None => task.logged_at.elapsed() >= LOG_INTERVAL,
+            };
+            if !due {
+                return;
+            }
+            if let Some(percent) = progress.percent() {
+                task.next_log = (percent / 10.0).floor() * 10.0 + 10.0;
+            }
+            task.logged_at = Instant::now();
+            let _ = writeln!(out, "[-] {}", status_line(task));
+        }
+        let _ = out.flush();
+    }
+}
+
+impl Drop for DownloadStatus {
+    fn drop(&mut self) {
+        let mut display = DISPLAY.lock().unwrap();
+        display.tasks.retain(|task| task.id != self.id);
+        if display.tty {
+            let mut out = display.output();
+            display.draw(&mut out);
+            let _ = out.flush();
+        }
+    }
+}
@@ -1,9 +1,13 @@
-use crate::{anime4you::Host, downloader::Downloader};
+use crate::{
+    anime4you::Host,
+    downloader::Downloader,
+    http::{HttpClient, Role},
+};
 use anyhow::{anyhow, Error};
 use regex::Regex;
 
-pub async fn new(url: &str) -> Result<Downloader, Error> {
-    let request = reqwest::get(url).await?;
+pub async fn new(http: &HttpClient, url: &str) -> Result<Downloader, Error> {
+    let request = http.get(Role::Hoster, url).send().await?;
     let site_source = request.text().await?;
     let regex = Regex::new(r#"type\|(.*?)\|(.*?)'"#).unwrap();
     let captures = regex.captures(&site_source);
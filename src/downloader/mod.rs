@@ -1,14 +1,177 @@
-use std::fs::File;
+use std::{
+    fs::{self, OpenOptions},
+    io,
+    path::{Path, PathBuf},
+    time::{Duration, Instant},
+};
 
 use anyhow::Error;
+use reqwest::{
+    header::{HeaderMap, CONTENT_RANGE, ETAG, IF_RANGE, RANGE},
+    StatusCode,
+};
+use serde::{Deserialize, Serialize};
+use tokio::{io::AsyncWriteExt, time::timeout};
 
-use crate::anime4you::Host;
+use crate::{anime4you::Host, cleanup::TempFile, http::HttpClient};
+pub use validate::{validate, InvalidVideo};
 
-pub mod streamtape;
 pub mod gounlimited;
+mod segments;
+pub mod streamtape;
+mod validate;
 pub mod vidoza;
 pub mod vivo;
 
+/// Smaller files aren't worth splitting into segments.
+const MIN_SEGMENTED_SIZE: u64 = 4 * 1024 * 1024;
+
+/// How often a running download reports its progress.
+const PROGRESS_INTERVAL: Duration = Duration::from_millis(500);
+
+/// The state of a running download, as reported to the progress callback.
+#[derive(Clone, Copy, Debug)]
+pub struct Progress {
+    pub downloaded: u64,
+    /// From the `Content-Length` header, if the hoster sends one.
+    pub total: Option<u64>,
+    /// Bytes per second, smoothed over the last few seconds.
+    pub speed: f64,
+    /// Set for the last report, once the transfer is complete.
+    pub finished: bool,
+}
+
+impl Progress {
+    pub fn percent(&self) -> Option<f64> {
+        self.total
+            .filter(|total| *total > 0)
+            .map(|total| self.downloaded as f64 * 100.0 / total as f64)
+    }
+}
+
+/// Keeps track of the progress and decides when it is due to be reported.
+struct ProgressMeter {
+    progress: Progress,
+    reported_at: Instant,
+    reported_bytes: u64,
+}
+
+impl ProgressMeter {
+    fn new(downloaded: u64, total: Option<u64>) -> ProgressMeter {
+        ProgressMeter {
+            progress: Progress {
+                downloaded,
+                total,
+                speed: 0.0,
+                finished: false,
+            },
+            reported_at: Instant::now(),
+            reported_bytes: downloaded,
+        }
+    }
+
+    /// Updates the downloaded bytes, returns the progress if it should be reported.
+    fn update(&mut self, downloaded: u64) -> Option<&Progress> {
+        self.progress.downloaded = downloaded;
+        let elapsed = self.reported_at.elapsed();
+        if elapsed < PROGRESS_INTERVAL {
+            return None;
+        }
+        let speed = downloaded.saturating_sub(self.reported_bytes) as f64 / elapsed.as_secs_f64();
+        // a moving average, so the speed doesn't jump around with every report
+        self.progress.speed = if self.progress.speed == 0.0 {
+            speed
+        } else {
+            self.progress.speed * 0.7 + speed * 0.3
+        };
+        self.reported_at = Instant::now();
+        self.reported_bytes = downloaded;
+        Some(&self.progress)
+    }
+
+    fn finish(&mut self) -> &Progress {
+        self.progress.finished = true;
+        &self.progress
+    }
+}
+
+/// The error of a transfer that received nothing for `stall_timeout`, retried like a dropped
+/// connection.
+pub(crate) fn stalled(stall_timeout: Duration) -> Error {
+    io::Error::new(
+        io::ErrorKind::TimedOut,
+        format!("Transfer stalled for {:?}", stall_timeout),
+    )
+    .into()
+}
+
+/// Where an unfinished download to `path` is kept.
+pub fn part_path(path: &Path) -> PathBuf {
+    let mut part = path.as_os_str().to_owned();
+    part.push(".part");
+    PathBuf::from(part)
+}
+
+/// Removes an unfinished download to `path`, along with its origin.
+pub fn remove_part(path: &Path) {
+    let part = part_path(path);
+    let _ = fs::remove_file(origin_path(&part));
+    let _ = fs::remove_file(&part);
+}
+
+fn origin_path(part: &Path) -> PathBuf {
+    let mut origin = part.as_os_str().to_owned();
+    origin.push(".origin");
+    PathBuf::from(origin)
+}
+
+/// Where a `.part` file came from, stored next to it so it is only resumed from the same file.
+#[derive(Debug, Serialize, Deserialize)]
+struct PartOrigin {
+    host: Host,
+    /// The full size of the video, if the server sent it.
+    total: Option<u64>,
+    /// Sent as `If-Range`, so the server starts over if the file changed in between.
+    etag: Option<String>,
+}
+
+impl PartOrigin {
+    fn load(part: &Path) -> Option<PartOrigin> {
+        let data = fs::read(origin_path(part)).ok()?;
+        serde_json::from_slice(&data).ok()
+    }
+
+    fn save(&self, part: &Path) -> Result<(), Error> {
+        fs::write(origin_path(part), serde_json::to_vec(self)?)?;
+        Ok(())
+    }
+
+    /// Whether a response announcing `total` bytes belongs to the same file.
+    fn same_total(&self, total: Option<u64>) -> bool {
+        self.total.map_or(true, |expected| total == Some(expected))
+    }
+}
+
+/// The first byte and the full size from a `Content-Range` header, i.e. `bytes 100-999/1000` or
+/// `bytes */1000`.
+fn content_range(headers: &HeaderMap) -> (Option<u64>, Option<u64>) {
+    let value = match headers
+        .get(CONTENT_RANGE)
+        .and_then(|value| value.to_str().ok())
+    {
+        Some(value) => value.trim(),
+        None => return (None, None),
+    };
+    let range = value.strip_prefix("bytes").unwrap_or(value).trim();
+    let mut parts = range.splitn(2, '/');
+    let start = parts
+        .next()
+        .and_then(|range| range.split('-').next())
+        .and_then(|start| start.trim().parse().ok());
+    let total = parts.next().and_then(|total| total.trim().parse().ok());
+    (start, total)
+}
+
 pub struct Downloader {
     pub video_url: String,
     pub file_name: String,
@@ -20,13 +183,195 @@ impl Downloader {
         String::from(self.file_name.split(".").last().unwrap())
     }
 
-    pub async fn download_to_file(&self, mut file: File) -> Result<(), Error> {
-        let video_url = self.video_url.clone();
-        tokio::task::spawn_blocking(move || -> Result<(), Error> {
-            let mut video = reqwest::blocking::get(video_url.as_str())?;
-            video.copy_to(&mut file)?;
-            Ok(())
-        }).await??;
+    /// Streams the video to `path`. The data is written to `<path>.part` first, which is
+    /// resumed if it already exists, came from the same hoster and file, and the server supports
+    /// ranges. It is only renamed once the transfer is complete. `on_progress` is called every
+    /// now and then and once at the end.
+    pub async fn download_to_file<F>(
+        &self,
+        http: &HttpClient,
+        path: &Path,
+        mut on_progress: F,
+    ) -> Result<(), Error>
+    where
+        F: FnMut(&Progress),
+    {
+        let part = part_path(path);
+        // a part from another hoster or file is started over
+        let origin = PartOrigin::load(&part).filter(|origin| origin.host == self.host);
+        let mut offset = match origin {
+            Some(_) => tokio::fs::metadata(&part)
+                .await
+                .map(|metadata| metadata.len())
+                .unwrap_or(0),
+            None => 0,
+        };
+        let connections = http.download_connections();
+        let mut video = loop {
+            let mut request = http.download(self.video_url.as_str());
+            if offset > 0 {
+                request = request.header(RANGE.as_str(), format!("bytes={}-", offset));
+                // weak tags aren't allowed in `If-Range`
+                if let Some(etag) = origin
+                    .as_ref()
+                    .and_then(|origin| origin.etag.as_ref())
+                    .filter(|etag| !etag.starts_with("W/"))
+                {
+                    request = request.header(IF_RANGE.as_str(), etag.as_str());
+                }
+            } else if connections > 1 {
+                // answered with 206 only if the server supports ranges
+                request = request.header(RANGE.as_str(), "bytes=0-");
+            }
+            let response = request.send().await?;
+            let range = content_range(response.headers());
+            match response.status() {
+                StatusCode::PARTIAL_CONTENT
+                    if offset == 0
+                        && range.0 == Some(0)
+                        && range.1.is_some_and(|total| total >= MIN_SEGMENTED_SIZE) =>
+                {
+                    let total = range.1.unwrap();
+                    remove_part(path);
+                    return segments::download(
+                        self,
+                        http,
+                        response,
+                        total,
+                        connections,
+                        path,
+                        ProgressMeter::new(0, Some(total)),
+                        on_progress,
+                    )
+                    .await;
+                }
+                StatusCode::PARTIAL_CONTENT
+                    if offset > 0
+                        && range.0 == Some(offset)
+                        && origin
+                            .as_ref()
+                            .is_some_and(|origin| origin.same_total(range.1)) =>
+                {
+                    break response;
+                }
+                StatusCode::RANGE_NOT_SATISFIABLE
+                    if offset > 0
+                        && range.1 == Some(offset)
+                        && origin
+                            .as_ref()
+                            .is_some_and(|origin| origin.same_total(range.1)) =>
+                {
+                    // the last run stopped right before renaming
+                    validate(&part, Some(offset))?;
+                    tokio::fs::rename(&part, path).await?;
+                    let _ = tokio::fs::remove_file(origin_path(&part)).await;
+                    return Ok(());
+                }
+                StatusCode::PARTIAL_CONTENT | StatusCode::RANGE_NOT_SATISFIABLE if offset > 0 => {
+                    offset = 0
+                }
+                _ => {
+                    // the server ignored the range, start over
+                    offset = 0;
+                    break response.error_for_status()?;
+                }
+            }
+        };
+        let total = content_range(video.headers())
+            .1
+            .or_else(|| video.content_length().map(|length| offset + length));
+        let mut meter = ProgressMeter::new(offset, total);
+        let mut downloaded = offset;
+        let file = OpenOptions::new()
+            .create(true)
+            .write(true)
+            .append(offset > 0)
+            .truncate(offset == 0)
+            .open(&part)?;
+        let _temp = TempFile::new(&part, true).with_sidecar(origin_path(&part));
+        if offset == 0 {
+            PartOrigin {
+                host: self.host,
+                total,
+                etag: video
+                    .headers()
+                    .get(ETAG)
+                    .and_then(|etag| etag.to_str().ok())
+                    .map(String::from),
+            }
+            .save(&part)?;
+        }
+        let mut file = tokio::fs::File::from_std(file);
+        let stall_timeout = http.stall_timeout();
+        let transfer = async {
+            loop {
+                let chunk = timeout(stall_timeout, video.chunk())
+                    .await
+                    .map_err(|_| stalled(stall_timeout))??;
+                match chunk {
+                    Some(chunk) => {
+                        file.write_all(&chunk).await?;
+                        downloaded += chunk.len() as u64;
+                    }
+                    None => break,
+                }
+                if let Some(progress) = meter.update(downloaded) {
+                    on_progress(progress);
+                }
+            }
+            // on disk before it is renamed, so a crash can't leave a truncated episode behind
+            file.sync_all().await?;
+            Ok::<(), Error>(())
+        }
+        .await;
+        drop(file);
+        if let Err(err) = transfer {
+            // only keep the part file if there is something to resume
+            if downloaded == 0 {
+                remove_part(path);
+            }
+            return Err(err);
+        }
+        validate(&part, meter.progress.total)?;
+        tokio::fs::rename(&part, path).await?;
+        let _ = tokio::fs::remove_file(origin_path(&part)).await;
+        on_progress(meter.finish());
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use reqwest::header::HeaderValue;
+
+    fn range(value: &str) -> (Option<u64>, Option<u64>) {
+        let mut headers = HeaderMap::new();
+        headers.insert(CONTENT_RANGE, HeaderValue::from_str(value).unwrap());
+        content_range(&headers)
+    }
+
+    #[test]
+    fn parses_content_range() {
+        assert_eq!(range("bytes 100-999/1000"), (Some(100), Some(1000)));
+        assert_eq!(range("bytes 0-0/5000000"), (Some(0), Some(5000000)));
+        assert_eq!(range(" bytes 100-999/1000 "), (Some(100), Some(1000)));
+        assert_eq!(range("bytes 100-999/*"), (Some(100), None));
+        assert_eq!(range("bytes */1000"), (None, Some(1000)));
+        assert_eq!(range("garbage"), (None, None));
+        assert_eq!(content_range(&HeaderMap::new()), (None, None));
+    }
+
+    #[test]
+    fn part_origin_compares_total() {
+        let origin = |total| PartOrigin {
+            host: Host::Vidoza,
+            total,
+            etag: None,
+        };
+        assert!(origin(Some(1000)).same_total(Some(1000)));
+        assert!(!origin(Some(1000)).same_total(Some(2000)));
+        assert!(!origin(Some(1000)).same_total(None));
+        assert!(origin(None).same_total(Some(1000)));
+    }
+}
@@ -0,0 +1,161 @@
+use std::{
+    io::{self, SeekFrom},
+    path::{Path, PathBuf},
+    sync::atomic::{AtomicBool, AtomicU64, Ordering},
+};
+
+use anyhow::{anyhow, Error};
+use futures::future::{join, try_join_all};
+use reqwest::{header::RANGE, StatusCode};
+use tokio::{
+    fs::OpenOptions,
+    io::{AsyncSeekExt, AsyncWriteExt},
+    time::{sleep, timeout},
+};
+
+use crate::{
+    cleanup::TempFile,
+    downloader::{
+        content_range, stalled, validate, Downloader, Progress, ProgressMeter, PROGRESS_INTERVAL,
+    },
+    http::{HttpClient, Response},
+    retry::is_interrupted,
+};
+
+/// Segments are written into place out of order, so unlike a `.part` file the result can't be
+/// resumed by its length.
+fn segments_path(path: &Path) -> PathBuf {
+    let mut segments = path.as_os_str().to_owned();
+    segments.push(".segments");
+    PathBuf::from(segments)
+}
+
+/// Downloads the file in `connections` byte ranges at once. `first` is the response to a
+/// `bytes=0-` request, it is used for the first segment.
+#[allow(clippy::too_many_arguments)]
+pub async fn download<F>(
+    downloader: &Downloader,
+    http: &HttpClient,
+    first: Response,
+    total: u64,
+    connections: usize,
+    path: &Path,
+    mut meter: ProgressMeter,
+    mut on_progress: F,
+) -> Result<(), Error>
+where
+    F: FnMut(&Progress),
+{
+    let temp = segments_path(path);
+    std::fs::File::create(&temp)?.set_len(total)?;
+    let _temp = TempFile::new(&temp, false);
+    let size = (total + connections as u64 - 1) / connections as u64;
+    let downloaded = AtomicU64::new(0);
+    let finished = AtomicBool::new(false);
+    let mut first = Some(first);
+    let segments = (0..connections as u64)
+        .map(|index| (index * size, ((index + 1) * size).min(total) - 1))
+        .filter(|(start, end)| start <= end)
+        .map(|(start, end)| {
+            fetch(
+                downloader,
+                http,
+                temp.as_path(),
+                start,
+                end,
+                first.take(),
+                &downloaded,
+            )
+        })
+        .collect::<Vec<_>>();
+    let segments = async {
+        let result = try_join_all(segments).await;
+        finished.store(true, Ordering::Relaxed);
+        result
+    };
+    let reporter = async {
+        while !finished.load(Ordering::Relaxed) {
+            sleep(PROGRESS_INTERVAL).await;
+            if let Some(progress) = meter.update(downloaded.load(Ordering::Relaxed)) {
+                on_progress(progress);
+            }
+        }
+    };
+    let (result, ()) = join(segments, reporter).await;
+    if let Err(err) = result.and_then(|_| validate(&temp, Some(total))) {
+        let _ = tokio::fs::remove_file(&temp).await;
+        return Err(err);
+    }
+    tokio::fs::rename(&temp, path).await?;
+    on_progress(meter.finish());
+    Ok(())
+}
+
+/// Downloads the bytes `start` to `end` (inclusive) into place, continuing from where the
+/// segment stopped if the transfer breaks off.
+async fn fetch(
+    downloader: &Downloader,
+    http: &HttpClient,
+    temp: &Path,
+    start: u64,
+    end: u64,
+    mut response: Option<Response>,
+    downloaded: &AtomicU64,
+) -> Result<(), Error> {
+    let mut file = OpenOptions::new().write(true).open(temp).await?;
+    let stall_timeout = http.stall_timeout();
+    let retry = http.retry();
+    let mut position = start;
+    let mut retries = 0;
+    loop {
+        let result = async {
+            let mut video = match response.take() {
+                Some(response) => response,
+                None => http
+                    .download(downloader.video_url.as_str())
+                    .header(RANGE.as_str(), format!("bytes={}-{}", position, end))
+                    .send()
+                    .await?
+                    .error_for_status()?,
+            };
+            if video.status() != StatusCode::PARTIAL_CONTENT
+                || content_range(video.headers()).0 != Some(position)
+            {
+                Err(anyhow!("The server didn't send the requested range"))?
+            }
+            file.seek(SeekFrom::Start(position)).await?;
+            while position <= end {
+                let chunk = timeout(stall_timeout, video.chunk())
+                    .await
+                    .map_err(|_| stalled(stall_timeout))??;
+                let chunk = match chunk {
+                    Some(chunk) => chunk,
+                    None => break,
+                };
+                // the first response isn't limited to the segment
+                let length = (chunk.len() as u64).min(end + 1 - position) as usize;
+                file.write_all(&chunk[..length]).await?;
+                position += length as u64;
+                downloaded.fetch_add(length as u64, Ordering::Relaxed);
+            }
+            if position <= end {
+                Err(io::Error::new(
+                    io::ErrorKind::UnexpectedEof,
+                    "Segment ended early",
+                ))?
+            }
+            Ok::<(), Error>(())
+        }
+        .await;
+        match result {
+            Ok(()) => break,
+            Err(err) if is_interrupted(&err) && retries < retry.retries => {
+                sleep(retry.backoff(retries)).await;
+                retries += 1;
+            }
+            Err(err) => return Err(err),
+        }
+    }
+    file.sync_all().await?;
+    Ok(())
+}
@@ -0,0 +1,176 @@
+use std::{
+    fmt,
+    fs::File,
+    io::{Read, Seek, SeekFrom},
+    path::Path,
+};
+
+use anyhow::Error;
+
+/// Anything smaller is an error page or an empty response rather than an episode.
+const MIN_VIDEO_SIZE: u64 = 1024 * 1024;
+const EBML_MAGIC: [u8; 4] = [0x1a, 0x45, 0xdf, 0xa3];
+const TS_PACKET_SIZE: usize = 188;
+
+/// A downloaded file that is not a complete video.
+#[derive(Debug)]
+pub struct InvalidVideo(String);
+
+impl fmt::Display for InvalidVideo {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Invalid video: {}", self.0)
+    }
+}
+
+impl std::error::Error for InvalidVideo {}
+
+fn invalid(reason: String) -> Error {
+    Error::new(InvalidVideo(reason))
+}
+
+/// Checks that `path` is plausibly a complete video: as large as announced by the server, not
+/// an HTML page, and a MP4, Matroska/WebM or MPEG-TS container.
+pub fn validate(path: &Path, expected: Option<u64>) -> Result<(), Error> {
+    let mut file = File::open(path)?;
+    let size = file.metadata()?.len();
+    if let Some(expected) = expected {
+        if size != expected {
+            Err(invalid(format!(
+                "expected {} bytes, got {}",
+                expected, size
+            )))?
+        }
+    }
+    let mut head = vec![0; 1024];
+    let read = file.read(&mut head)?;
+    head.truncate(read);
+    let text = String::from_utf8_lossy(&head).to_lowercase();
+    let text = text.trim_start();
+    if text.starts_with("<!doctype") || text.starts_with("<html") || text.starts_with("<?xml") {
+        Err(invalid("the hoster sent a web page".to_string()))?
+    }
+    if size < MIN_VIDEO_SIZE {
+        Err(invalid(format!("only {} bytes", size)))?
+    }
+    let matroska = head.starts_with(&EBML_MAGIC);
+    let transport_stream =
+        head.len() > TS_PACKET_SIZE && head[0] == 0x47 && head[TS_PACKET_SIZE] == 0x47;
+    if head.len() >= 8 && &head[4..8] == b"ftyp" {
+        check_mp4(&mut file, size)
+    } else if matroska || transport_stream {
+        Ok(())
+    } else {
+        Err(invalid("unknown container format".to_string()))
+    }
+}
+
+/// Walks the top level boxes, they have to end exactly at the end of the file and contain the
+/// `moov` box, without it the video can't be played.
+fn check_mp4(file: &mut File, size: u64) -> Result<(), Error> {
+    let mut position = 0;
+    let mut moov = false;
+    while position < size {
+        let mut header = [0; 16];
+        file.seek(SeekFrom::Start(position))?;
+        file.read_exact(&mut header[..8])
+            .map_err(|_| invalid("truncated box header".to_string()))?;
+        let mut length = u32::from_be_bytes([header[0], header[1], header[2], header[3]]) as u64;
+        let moov_box = &header[4..8] == b"moov";
+        if length == 1 {
+            file.read_exact(&mut header[8..16])
+                .map_err(|_| invalid("truncated box header".to_string()))?;
+            let mut large = [0; 8];
+            large.copy_from_slice(&header[8..16]);
+            length = u64::from_be_bytes(large);
+        } else if length == 0 {
+            // the box extends to the end of the file
+            length = size - position;
+        }
+        if length < 8 {
+            Err(invalid(format!("broken box at byte {}", position)))?
+        }
+        moov |= moov_box;
+        position = position.saturating_add(length);
+    }
+    if position > size {
+        Err(invalid(format!(
+            "truncated, the last box ends {} bytes after the end of the file",
+            position - size
+        )))?
+    }
+    if !moov {
+        Err(invalid("no moov box".to_string()))?
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+
+    fn mp4_box(kind: &[u8; 4], payload: usize) -> Vec<u8> {
+        let mut data = ((payload + 8) as u32).to_be_bytes().to_vec();
+        data.extend_from_slice(kind);
+        data.resize(payload + 8, 0);
+        data
+    }
+
+    fn check(boxes: &[Vec<u8>]) -> Result<(), Error> {
+        let mut file = tempfile::tempfile().unwrap();
+        file.write_all(&boxes.concat()).unwrap();
+        let size = file.metadata().unwrap().len();
+        check_mp4(&mut file, size)
+    }
+
+    #[test]
+    fn accepts_complete_mp4() {
+        let boxes = [
+            mp4_box(b"ftyp", 16),
+            mp4_box(b"moov", 100),
+            mp4_box(b"mdat", 1000),
+        ];
+        assert!(check(&boxes).is_ok());
+    }
+
+    #[test]
+    fn accepts_large_and_open_ended_boxes() {
+        let mut large = 1u32.to_be_bytes().to_vec();
+        large.extend_from_slice(b"mdat");
+        large.extend_from_slice(&1016u64.to_be_bytes());
+        large.resize(1016, 0);
+        let boxes = [mp4_box(b"ftyp", 16), mp4_box(b"moov", 100), large];
+        assert!(check(&boxes).is_ok());
+
+        let mut open_ended = mp4_box(b"mdat", 1000);
+        open_ended[..4].copy_from_slice(&0u32.to_be_bytes());
+        let boxes = [mp4_box(b"ftyp", 16), mp4_box(b"moov", 100), open_ended];
+        assert!(check(&boxes).is_ok());
+    }
+
+    #[test]
+    fn rejects_mp4_without_moov() {
+        let boxes = [mp4_box(b"ftyp", 16), mp4_box(b"mdat", 1000)];
+        let err = check(&boxes).unwrap_err();
+        assert!(err.is::<InvalidVideo>());
+    }
+
+    #[test]
+    fn rejects_truncated_mp4() {
+        let mut mdat = mp4_box(b"mdat", 1000);
+        mdat.truncate(500);
+        let boxes = [mp4_box(b"ftyp", 16), mp4_box(b"moov", 100), mdat];
+        assert!(check(&boxes).unwrap_err().is::<InvalidVideo>());
+
+        let boxes = [mp4_box(b"ftyp", 16), mp4_box(b"moov", 100), vec![0, 0, 1]];
+        assert!(check(&boxes).unwrap_err().is::<InvalidVideo>());
+    }
+
+    #[test]
+    fn rejects_broken_box() {
+        let mut broken = mp4_box(b"free", 8);
+        broken[..4].copy_from_slice(&4u32.to_be_bytes());
+        let boxes = [mp4_box(b"ftyp", 16), mp4_box(b"moov", 100), broken];
+        assert!(check(&boxes).unwrap_err().is::<InvalidVideo>());
+    }
+}
@@ -1,4 +1,8 @@
-use crate::{anime4you::Host, downloader::Downloader};
+use crate::{
+    anime4you::Host,
+    downloader::Downloader,
+    http::{HttpClient, Role},
+};
 use anyhow::{anyhow, Error};
 use regex::Regex;
 
@@ -8,7 +12,7 @@ fn caesar(input: String, alphabet: &str, shift: i32) -> String {
     for c in input.chars() {
         if alphabet.contains(c) {
             out.push(
-                alphabet.as_bytes()[((alphabet.find(c).unwrap() + shift as usize) % len)] as char,
+                alphabet.as_bytes()[(alphabet.find(c).unwrap() + shift as usize) % len] as char,
             );
         } else {
             out.push(c);
@@ -21,9 +25,9 @@ fn rot47(input: String) -> String {
     caesar(input, "!\"#$%&'()*+,-./0123456789:;<=>?@ABCDEFGHIJKLMNOPQRSTUVWXYZ[\\]^_`abcdefghijklmnopqrstuvwxyz{|}~", 47)
 }
 
-pub async fn new(url: &str) -> Result<Downloader, Error> {
+pub async fn new(http: &HttpClient, url: &str) -> Result<Downloader, Error> {
     let url = url.replace("embed/", "");
-    let request = reqwest::get(&url).await?;
+    let request = http.get(Role::Hoster, &url).send().await?;
     let site_source = request.text().await?;
     let source_regex =
         Regex::new(r#"(?s)InitializeStream\s*\(\s*\{.+source:\s*'([A-Za-z0-9%_]+)',"#).unwrap();
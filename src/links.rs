@@ -0,0 +1,92 @@
+use std::io::Write;
+
+use anyhow::{anyhow, Error};
+use serde::Serialize;
+
+use crate::anime4you::Host;
+
+/// A hoster link together with the media URL it resolved to, if any.
+#[derive(Clone, Debug, Serialize)]
+pub struct ResolvedLink {
+    pub host: Host,
+    pub label: String,
+    pub url: String,
+    pub media_url: Option<String>,
+    pub file_name: Option<String>,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct EpisodeLinks {
+    pub series: String,
+    pub series_id: u32,
+    pub language: String,
+    pub episode: u32,
+    /// In the order they would be tried when downloading.
+    pub links: Vec<ResolvedLink>,
+}
+
+impl EpisodeLinks {
+    /// The URL another downloader should use: the first media URL, or the first hoster page if
+    /// none could be resolved.
+    pub fn best_url(&self) -> Option<&str> {
+        self.links
+            .iter()
+            .find_map(|link| link.media_url.as_deref())
+            .or_else(|| self.links.first().map(|link| link.url.as_str()))
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LinkFormat {
+    Json,
+    Text,
+    M3u,
+}
+
+impl LinkFormat {
+    pub fn from_name(name: &str) -> Result<LinkFormat, Error> {
+        match name.to_lowercase().as_str() {
+            "json" => Ok(LinkFormat::Json),
+            "text" | "txt" => Ok(LinkFormat::Text),
+            "m3u" | "m3u8" => Ok(LinkFormat::M3u),
+            _ => Err(anyhow!("Unknown link format \"{}\"", name)),
+        }
+    }
+
+    /// Guesses the format from the extension of the export file, plain text otherwise.
+    pub fn from_path(path: &str) -> LinkFormat {
+        path.rsplit('.')
+            .next()
+            .and_then(|extension| LinkFormat::from_name(extension).ok())
+            .unwrap_or(LinkFormat::Text)
+    }
+
+    pub fn write<W: Write>(self, mut writer: W, episodes: &[EpisodeLinks]) -> Result<(), Error> {
+        match self {
+            LinkFormat::Json => {
+                serde_json::to_writer_pretty(&mut writer, episodes)?;
+                writeln!(writer)?;
+            }
+            LinkFormat::Text => {
+                for url in episodes.iter().filter_map(EpisodeLinks::best_url) {
+                    writeln!(writer, "{}", url)?;
+                }
+            }
+            LinkFormat::M3u => {
+                writeln!(writer, "#EXTM3U")?;
+                for episode in episodes {
+                    if let Some(url) = episode.best_url() {
+                        writeln!(
+                            writer,
+                            "#EXTINF:-1,{} - Episode {}",
+                            episode.series, episode.episode
+                        )?;
+                        writeln!(writer, "{}", url)?;
+                    }
+                }
+            }
+        }
+        writer.flush()?;
+        Ok(())
+    }
+}
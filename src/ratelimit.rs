@@ -0,0 +1,226 @@
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use serde::{Deserialize, Serialize};
+use tokio::{
+    sync::{OwnedSemaphorePermit, Semaphore},
+    time::sleep,
+};
+
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct HostLimitConfig {
+    pub requests_per_minute: Option<u32>,
+    pub connections: Option<usize>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct RateLimitConfig {
+    /// Requests per minute allowed to a single host, 0 disables the limit.
+    pub requests_per_minute: u32,
+    /// Requests that may be sent to a host at once, before the limit kicks in.
+    pub burst: u32,
+    /// Connections open to a single host at the same time, including running downloads.
+    pub connections: usize,
+    /// Overrides for single hosts, i.e. `vidoza.net`.
+    pub hosts: HashMap<String, HostLimitConfig>,
+}
+
+impl Default for RateLimitConfig {
+    fn default() -> RateLimitConfig {
+        RateLimitConfig {
+            requests_per_minute: 60,
+            burst: 5,
+            connections: 4,
+            hosts: HashMap::new(),
+        }
+    }
+}
+
+struct Bucket {
+    tokens: f64,
+    capacity: f64,
+    per_second: f64,
+    updated: Instant,
+}
+
+impl Bucket {
+    /// Takes a token, or returns how long to wait until the next one is available.
+    fn take(&mut self) -> Option<Duration> {
+        if self.per_second <= 0.0 {
+            return None;
+        }
+        let now = Instant::now();
+        self.tokens = (self.tokens
+            + now.duration_since(self.updated).as_secs_f64() * self.per_second)
+            .min(self.capacity);
+        self.updated = now;
+        if self.tokens >= 1.0 {
+            self.tokens -= 1.0;
+            None
+        } else {
+            Some(Duration::from_secs_f64(
+                (1.0 - self.tokens) / self.per_second,
+            ))
+        }
+    }
+}
+
+struct HostLimit {
+    bucket: Mutex<Bucket>,
+    connections: Arc<Semaphore>,
+}
+
+/// A token bucket and connection limit for every host contacted.
+pub struct RateLimiter {
+    config: RateLimitConfig,
+    hosts: Mutex<HashMap<String, Arc<HostLimit>>>,
+}
+
+impl RateLimiter {
+    pub fn new(config: RateLimitConfig) -> RateLimiter {
+        RateLimiter {
+            config,
+            hosts: Mutex::new(HashMap::new()),
+        }
+    }
+
+    fn host(&self, host: &str) -> Arc<HostLimit> {
+        let mut hosts = self.hosts.lock().unwrap();
+        hosts
+            .entry(host.to_owned())
+            .or_insert_with(|| {
+                let overrides = self
+                    .config
+                    .hosts
+                    .iter()
+                    .find(|(name, _)| host == *name || host.ends_with(&format!(".{}", name)))
+                    .map(|(_, limit)| limit.clone())
+                    .unwrap_or_default();
+                let requests_per_minute = overrides
+                    .requests_per_minute
+                    .unwrap_or(self.config.requests_per_minute);
+                let capacity = self.config.burst.max(1) as f64;
+                Arc::new(HostLimit {
+                    bucket: Mutex::new(Bucket {
+                        tokens: capacity,
+                        capacity,
+                        per_second: requests_per_minute as f64 / 60.0,
+                        updated: Instant::now(),
+                    }),
+                    connections: Arc::new(Semaphore::new(
+                        overrides
+                            .connections
+                            .unwrap_or(self.config.connections)
+                            .max(1),
+                    )),
+                })
+            })
+            .clone()
+    }
+
+    /// Waits until a request to `host` is allowed. The connection slot is held until the
+    /// returned permit is dropped.
+    pub async fn acquire(&self, host: &str) -> OwnedSemaphorePermit {
+        let limit = self.host(host);
+        let permit = limit
+            .connections
+            .clone()
+            .acquire_owned()
+            .await
+            .expect("host semaphore is never closed");
+        loop {
+            let wait = limit.bucket.lock().unwrap().take();
+            match wait {
+                Some(wait) => sleep(wait).await,
+                None => return permit,
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn bucket(capacity: f64, per_second: f64) -> Bucket {
+        Bucket {
+            tokens: capacity,
+            capacity,
+            per_second,
+            updated: Instant::now(),
+        }
+    }
+
+    #[test]
+    fn bucket_allows_bursts_then_waits() {
+        let mut bucket = bucket(3.0, 1.0);
+        for _ in 0..3 {
+            assert_eq!(bucket.take(), None);
+        }
+        let wait = bucket.take().unwrap();
+        assert!(wait > Duration::from_millis(900) && wait <= Duration::from_secs(1));
+    }
+
+    #[test]
+    fn bucket_refills_up_to_capacity() {
+        let mut bucket = bucket(2.0, 1.0);
+        bucket.tokens = 0.0;
+        bucket.updated = Instant::now() - Duration::from_secs(60);
+        assert_eq!(bucket.take(), None);
+        assert_eq!(bucket.take(), None);
+        assert!(bucket.take().is_some());
+    }
+
+    #[test]
+    fn zero_rate_disables_the_limit() {
+        let mut bucket = bucket(1.0, 0.0);
+        for _ in 0..100 {
+            assert_eq!(bucket.take(), None);
+        }
+    }
+
+    #[test]
+    fn applies_host_overrides_to_subdomains() {
+        let mut config = RateLimitConfig::default();
+        config.hosts.insert(
+            "vidoza.net".to_owned(),
+            HostLimitConfig {
+                requests_per_minute: Some(6),
+                connections: Some(2),
+            },
+        );
+        let limiter = RateLimiter::new(config);
+        for host in ["vidoza.net", "str1.vidoza.net"] {
+            let limit = limiter.host(host);
+            assert_eq!(limit.bucket.lock().unwrap().per_second, 0.1, "{}", host);
+            assert_eq!(limit.connections.available_permits(), 2, "{}", host);
+        }
+        let limit = limiter.host("notvidoza.net");
+        assert_eq!(limit.bucket.lock().unwrap().per_second, 1.0);
+        assert_eq!(limit.connections.available_permits(), 4);
+    }
+
+    #[tokio::test]
+    async fn holds_connections_until_the_permit_is_dropped() {
+        let limiter = RateLimiter::new(RateLimitConfig {
+            requests_per_minute: 0,
+            connections: 1,
+            ..RateLimitConfig::default()
+        });
+        let permit = limiter.acquire("example.com").await;
+        assert_eq!(
+            limiter.host("example.com").connections.available_permits(),
+            0
+        );
+        drop(permit);
+        assert_eq!(
+            limiter.host("example.com").connections.available_permits(),
+            1
+        );
+    }
+}
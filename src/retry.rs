@@ -0,0 +1,223 @@
+use std::{
+    io,
+    time::{Duration, SystemTime},
+};
+
+use anyhow::Error;
+use rand::Rng;
+use reqwest::{
+    header::{HeaderMap, RETRY_AFTER},
+    StatusCode,
+};
+use serde::{Deserialize, Serialize};
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct RetryConfig {
+    /// How often a failed request is repeated, 0 disables retries.
+    pub retries: u32,
+    /// Milliseconds waited before the first retry, doubled with every further one.
+    pub base_delay: u64,
+    /// Upper bound in milliseconds for the wait between two attempts.
+    pub max_delay: u64,
+    /// Seconds a server may ask to wait with `Retry-After`, longer waits give up instead.
+    pub max_retry_after: u64,
+}
+
+impl Default for RetryConfig {
+    fn default() -> RetryConfig {
+        RetryConfig {
+            retries: 3,
+            base_delay: 1000,
+            max_delay: 30000,
+            max_retry_after: 300,
+        }
+    }
+}
+
+impl RetryConfig {
+    /// The time to wait before the given retry (starting at 0). A `Retry-After` sent by the
+    /// server is honoured as it is, `None` means it asks for longer than we are willing to wait.
+    pub fn delay(&self, retry: u32, retry_after: Option<Duration>) -> Option<Duration> {
+        match retry_after {
+            Some(retry_after) if retry_after > Duration::from_secs(self.max_retry_after) => None,
+            Some(retry_after) => Some(retry_after),
+            None => Some(self.backoff(retry)),
+        }
+    }
+
+    /// The exponential backoff before the given retry, with jitter.
+    pub fn backoff(&self, retry: u32) -> Duration {
+        let backoff = Duration::from_millis(self.base_delay.saturating_mul(1 << retry.min(16)))
+            .min(Duration::from_millis(self.max_delay));
+        // full jitter on the upper half, so parallel tasks don't retry in lockstep
+        backoff.mul_f64(rand::thread_rng().gen_range(0.5..=1.0))
+    }
+}
+
+/// Whether a response status is worth retrying.
+pub fn is_transient_status(status: StatusCode) -> bool {
+    status == StatusCode::TOO_MANY_REQUESTS
+        || status == StatusCode::REQUEST_TIMEOUT
+        || status.is_server_error()
+}
+
+/// Whether a failed request might succeed when sent again.
+pub fn is_transient_request(error: &reqwest::Error) -> bool {
+    match error.status() {
+        Some(status) => is_transient_status(status),
+        None => error.is_timeout() || error.is_connect() || error.is_body() || error.is_request(),
+    }
+}
+
+/// Whether an error was caused by the network or an overloaded server and might go away when
+/// trying again. Client errors and responses that couldn't be parsed are not retried.
+pub fn is_transient(error: &Error) -> bool {
+    error.chain().any(|cause| {
+        if let Some(error) = cause.downcast_ref::<reqwest::Error>() {
+            is_transient_request(error)
+        } else if let Some(error) = cause.downcast_ref::<io::Error>() {
+            matches!(
+                error.kind(),
+                io::ErrorKind::ConnectionReset
+                    | io::ErrorKind::ConnectionAborted
+                    | io::ErrorKind::ConnectionRefused
+                    | io::ErrorKind::BrokenPipe
+                    | io::ErrorKind::TimedOut
+                    | io::ErrorKind::UnexpectedEof
+                    | io::ErrorKind::Interrupted
+            )
+        } else {
+            false
+        }
+    })
+}
+
+/// Whether a transfer broke off after the response had started. Failed requests are already
+/// retried by `HttpRequest::send`, only this is worth another attempt on top of that.
+pub fn is_interrupted(error: &Error) -> bool {
+    error.chain().any(|cause| {
+        if let Some(error) = cause.downcast_ref::<reqwest::Error>() {
+            error.status().is_none() && (error.is_body() || error.is_decode())
+        } else if let Some(error) = cause.downcast_ref::<io::Error>() {
+            matches!(
+                error.kind(),
+                io::ErrorKind::ConnectionReset
+                    | io::ErrorKind::ConnectionAborted
+                    | io::ErrorKind::BrokenPipe
+                    | io::ErrorKind::TimedOut
+                    | io::ErrorKind::UnexpectedEof
+                    | io::ErrorKind::Interrupted
+            )
+        } else {
+            false
+        }
+    })
+}
+
+/// Whether the server refused a request, i.e. because a link expired.
+pub fn is_client_error(error: &Error) -> bool {
+    error.chain().any(|cause| {
+        cause
+            .downcast_ref::<reqwest::Error>()
+            .and_then(|error| error.status())
+            .map(|status| status.is_client_error())
+            .unwrap_or(false)
+    })
+}
+
+/// Parses a `Retry-After` header, given either in seconds or as HTTP date.
+pub fn retry_after(headers: &HeaderMap) -> Option<Duration> {
+    let value = headers.get(RETRY_AFTER)?.to_str().ok()?.trim();
+    if let Ok(seconds) = value.parse::<u64>() {
+        return Some(Duration::from_secs(seconds));
+    }
+    httpdate::parse_http_date(value)
+        .ok()?
+        .duration_since(SystemTime::now())
+        .ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use reqwest::header::HeaderValue;
+
+    fn headers(retry_after: &str) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert(RETRY_AFTER, HeaderValue::from_str(retry_after).unwrap());
+        headers
+    }
+
+    #[test]
+    fn classifies_statuses() {
+        for status in [429, 408, 500, 502, 503, 504] {
+            assert!(
+                is_transient_status(StatusCode::from_u16(status).unwrap()),
+                "{}",
+                status
+            );
+        }
+        for status in [200, 206, 301, 400, 403, 404, 416] {
+            assert!(
+                !is_transient_status(StatusCode::from_u16(status).unwrap()),
+                "{}",
+                status
+            );
+        }
+    }
+
+    #[test]
+    fn classifies_io_errors() {
+        let error = |kind| Error::new(io::Error::new(kind, "test"));
+        let interrupted = error(io::ErrorKind::ConnectionReset);
+        assert!(is_interrupted(&interrupted) && is_transient(&interrupted));
+        let refused = error(io::ErrorKind::ConnectionRefused);
+        assert!(!is_interrupted(&refused) && is_transient(&refused));
+        let missing = error(io::ErrorKind::NotFound);
+        assert!(!is_interrupted(&missing) && !is_transient(&missing));
+        let wrapped = error(io::ErrorKind::TimedOut).context("Transfer failed");
+        assert!(is_interrupted(&wrapped));
+    }
+
+    #[test]
+    fn parses_retry_after() {
+        assert_eq!(retry_after(&headers("120")), Some(Duration::from_secs(120)));
+        assert_eq!(retry_after(&headers(" 0 ")), Some(Duration::from_secs(0)));
+        let date = httpdate::fmt_http_date(SystemTime::now() + Duration::from_secs(600));
+        let wait = retry_after(&headers(date.as_str())).unwrap();
+        assert!(wait > Duration::from_secs(590) && wait <= Duration::from_secs(600));
+        assert_eq!(retry_after(&headers("Wed, 21 Oct 2015 07:28:00 GMT")), None);
+        assert_eq!(retry_after(&headers("soon")), None);
+        assert_eq!(retry_after(&headers("-5")), None);
+        assert_eq!(retry_after(&HeaderMap::new()), None);
+    }
+
+    #[test]
+    fn honours_retry_after_up_to_the_limit() {
+        let config = RetryConfig::default();
+        let wait = Duration::from_secs(config.max_retry_after);
+        assert_eq!(config.delay(0, Some(wait)), Some(wait));
+        assert_eq!(
+            config.delay(5, Some(Duration::from_secs(1))),
+            Some(Duration::from_secs(1))
+        );
+        assert_eq!(config.delay(0, Some(wait + Duration::from_secs(1))), None);
+        assert!(config.delay(0, None).is_some());
+    }
+
+    #[test]
+    fn caps_backoff() {
+        let config = RetryConfig::default();
+        for retry in 0..40 {
+            let backoff = config.backoff(retry);
+            let expected = Duration::from_millis(
+                config
+                    .base_delay
+                    .saturating_mul(1 << retry.min(16))
+                    .min(config.max_delay),
+            );
+            assert!(backoff >= expected / 2 && backoff <= expected, "{}", retry);
+        }
+    }
+}